@@ -0,0 +1,145 @@
+//! [`TracingAllocator`]: print a backtrace when an allocation fails.
+
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    io::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    buf::{StackWriter, REPORT_BUF_SIZE},
+    get_backtrace_style, lock,
+    trace::{self, DisplayFrames, Frame, MAX_FRAMES},
+    BacktraceStyle,
+};
+
+/// The [`lock::thread_id`] of the thread reporting a failed allocation, or `0`
+/// if none is. Not a thread-local: inside SGX, a thread-local's first use
+/// allocates, which would land us right back in here.
+static REPORTING: AtomicUsize = AtomicUsize::new(0);
+
+/// A [`GlobalAlloc`] wrapper that prints a raw backtrace whenever the inner
+/// allocator fails an allocation.
+///
+/// Allocation failures don't go through the panic hook: std just prints
+/// `memory allocation of N bytes failed` and aborts the enclave. Wrapping the
+/// global allocator gets us a backtrace of the failed allocation first, in the
+/// same `stack backtrace:` format as the panic hook:
+///
+/// ```text
+/// enclave alloc error: memory allocation of 1048576 bytes (align 8) failed
+/// stack backtrace:
+///    0: 0x4a1c2e
+///    1: 0x1b09d9
+/// ...
+/// ```
+///
+/// Like the panic hook, reporting doesn't allocate. The report goes to stderr,
+/// right before std's own message.
+///
+/// Fallible allocations (e.g. `Vec::try_reserve`) that fail get reported too,
+/// even if the caller handles the failure.
+///
+/// ```rust
+/// use sgx_panic_backtrace::TracingAllocator;
+/// use std::alloc::System;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: TracingAllocator<System> = TracingAllocator::new(System);
+/// ```
+#[derive(Debug, Default)]
+pub struct TracingAllocator<A> {
+    inner: A,
+}
+
+impl<A> TracingAllocator<A> {
+    /// Wrap the `inner` allocator.
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TracingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if ptr.is_null() {
+            report_alloc_failure(layout, Self::alloc as *const () as usize);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if ptr.is_null() {
+            report_alloc_failure(layout, Self::alloc_zeroed as *const () as usize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if new_ptr.is_null() {
+            // `realloc`'s contract guarantees this is a valid layout.
+            let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+            report_alloc_failure(new_layout, Self::realloc as *const () as usize);
+        }
+        new_ptr
+    }
+}
+
+/// Trace the stack and write out the report for a failed allocation. `marker`
+/// is the address of the allocator method that failed.
+#[cold]
+#[inline(never)]
+fn report_alloc_failure(layout: Layout, marker: usize) {
+    // anything in here allocating (and failing) again shouldn't recurse.
+    let me = lock::thread_id();
+    if REPORTING.load(Ordering::Relaxed) == me {
+        return;
+    }
+    // don't interleave with any panic reports or other failed allocations.
+    let _guard = lock::REPORTS.lock();
+    REPORTING.store(me, Ordering::Relaxed);
+
+    let mut frames = [Frame::default(); MAX_FRAMES];
+    let num_frames = trace::capture_frames(&mut frames);
+    let frames = match get_backtrace_style() {
+        BacktraceStyle::Off => &[],
+        BacktraceStyle::Short => {
+            let frames = trace::frames_after(
+                &frames[..num_frames],
+                report_alloc_failure as *const () as usize,
+            );
+            // the allocator method might've been inlined into its caller.
+            trace::frames_after(frames, marker)
+        }
+        BacktraceStyle::Full => &frames[..num_frames],
+    };
+
+    // ignore any errors; std's about to abort anyway.
+    let _ = write_report(&mut io::stderr().lock(), layout, frames);
+
+    // unless we gave up waiting for the lock and another thread took over.
+    let _ = REPORTING.compare_exchange(me, 0, Ordering::Relaxed, Ordering::Relaxed);
+}
+
+fn write_report<W: Write>(out: &mut W, layout: Layout, frames: &[Frame]) -> io::Result<()> {
+    let mut out = StackWriter::<_, REPORT_BUF_SIZE>::new(out);
+    writeln!(
+        out,
+        "enclave alloc error: memory allocation of {} bytes (align {}) failed",
+        layout.size(),
+        layout.align(),
+    )?;
+    writeln!(out, "{}", DisplayFrames(frames))?;
+    out.flush()
+}
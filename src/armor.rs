@@ -0,0 +1,149 @@
+//! ASCII armor for encrypted panic reports, so the ciphertext survives being
+//! passed through line-oriented logs:
+//!
+//! ```text
+//! -----BEGIN SGX PANIC REPORT-----
+//! <base64, 64 chars per line>
+//! -----END SGX PANIC REPORT-----
+//! ```
+
+use std::io::{self, Write};
+
+pub(crate) const BEGIN: &str = "-----BEGIN SGX PANIC REPORT-----";
+pub(crate) const END: &str = "-----END SGX PANIC REPORT-----";
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Bytes per base64 line. Encodes to 64 chars.
+const LINE_BYTES: usize = 48;
+
+/// Write out `bytes` as an armored, base64-encoded block.
+pub(crate) fn write_armored<W: Write>(out: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writeln!(out, "{BEGIN}")?;
+    for line in bytes.chunks(LINE_BYTES) {
+        let mut encoded = [0u8; LINE_BYTES / 3 * 4];
+        let mut len = 0;
+        for chunk in line.chunks(3) {
+            let b = [
+                chunk[0],
+                chunk.get(1).copied().unwrap_or(0),
+                chunk.get(2).copied().unwrap_or(0),
+            ];
+            let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+            for (i, out) in encoded[len..len + 4].iter_mut().enumerate() {
+                *out = if i <= chunk.len() {
+                    ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f]
+                } else {
+                    b'='
+                };
+            }
+            len += 4;
+        }
+        out.write_all(&encoded[..len])?;
+        writeln!(out)?;
+    }
+    writeln!(out, "{END}")
+}
+
+/// Decode the base64 body of an armored block. Whitespace is ignored. Returns
+/// `None` if it's not valid base64.
+#[cfg(feature = "host")]
+pub(crate) fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    fn decode_char(c: u8) -> Option<u32> {
+        ALPHABET.iter().position(|x| *x == c).map(|x| x as u32)
+    }
+
+    let text = text
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect::<Vec<_>>();
+    if text.len() % 4 != 0 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let num_chunks = text.len() / 4;
+    for (chunk_idx, chunk) in text.chunks(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        // only the last chunk can be padded
+        if padding > 2 || (padding > 0 && chunk_idx + 1 != num_chunks) {
+            return None;
+        }
+        let mut n: u32 = 0;
+        for c in &chunk[..4 - padding] {
+            n = (n << 6) | decode_char(*c)?;
+        }
+        n <<= 6 * padding;
+        bytes.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(bytes)
+}
+
+#[cfg(all(test, feature = "host"))]
+mod tests {
+    use super::*;
+
+    fn armored(bytes: &[u8]) -> String {
+        let mut out = Vec::new();
+        write_armored(&mut out, bytes).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn body(armored: &str) -> &str {
+        armored
+            .strip_prefix(BEGIN)
+            .unwrap()
+            .trim_end()
+            .strip_suffix(END)
+            .unwrap()
+    }
+
+    #[test]
+    fn rfc4648_vectors() {
+        for (bytes, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"fooba", "Zm9vYmE="),
+            (b"foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(body(&armored(bytes)).trim(), encoded);
+            assert_eq!(decode_base64(encoded).as_deref(), Some(bytes));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        // every padding length, and lines that are empty, partial, and full
+        let bytes = (0..=255)
+            .cycle()
+            .take(3 * LINE_BYTES + 7)
+            .collect::<Vec<u8>>();
+        for len in 0..bytes.len() {
+            let armored = armored(&bytes[..len]);
+            assert!(armored.starts_with(BEGIN) && armored.ends_with(&format!("{END}\n")));
+            assert!(armored.lines().all(|line| line.len() <= 64));
+            assert_eq!(
+                decode_base64(body(&armored)).as_deref(),
+                Some(&bytes[..len]),
+                "{len}"
+            );
+        }
+    }
+
+    #[test]
+    fn bad_base64() {
+        for text in [
+            "Zm9", "Zm9vY", "Zm9v!mFy", "Zm9vYmF-", "Z===", "====", "Zg=v", "Zg==Zm9v", "Zm8=Zm8=",
+        ] {
+            assert_eq!(decode_base64(text), None, "{text:?}");
+        }
+        // whitespace (e.g. line breaks and CRs) is fine
+        assert_eq!(
+            decode_base64(" Zm9v\r\nYmFy\n").as_deref(),
+            Some(&b"foobar"[..])
+        );
+    }
+}
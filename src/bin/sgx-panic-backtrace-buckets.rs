@@ -0,0 +1,69 @@
+//! Group the panic reports in a batch of enclave logs by crash fingerprint.
+//!
+//! ```bash
+//! $ sgx-panic-backtrace-buckets logs/*.log
+//! ```
+
+use std::{
+    env,
+    fs::File,
+    io::{self, BufReader},
+    process::ExitCode,
+};
+
+use sgx_panic_backtrace::host::CrashBuckets;
+
+const USAGE: &str = "\
+usage: sgx-panic-backtrace-buckets [<log-file>...]
+
+Reads enclave logs from each <log-file> (or stdin) and prints one bucket per
+unique crash fingerprint, most common first, with its number of reports and
+the first report as an example.
+
+Encrypted reports need to be decrypted first with
+`sgx-panic-backtrace-resolve --key`.";
+
+fn main() -> ExitCode {
+    let log_paths = env::args().skip(1).collect::<Vec<_>>();
+    if let [arg] = log_paths.as_slice() {
+        if arg == "-h" || arg == "--help" {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+    }
+    if log_paths.iter().any(|arg| arg.starts_with('-')) {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    }
+
+    let mut buckets = CrashBuckets::new();
+    if log_paths.is_empty() {
+        if let Err(err) = buckets.read_reports(io::stdin().lock()) {
+            eprintln!("error: failed to read stdin: {err}");
+            return ExitCode::FAILURE;
+        }
+    }
+    for log_path in &log_paths {
+        let result =
+            File::open(log_path).and_then(|file| buckets.read_reports(BufReader::new(file)));
+        if let Err(err) = result {
+            eprintln!("error: failed to read '{log_path}': {err}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    for bucket in buckets.into_buckets() {
+        let reports = if bucket.count == 1 {
+            "report"
+        } else {
+            "reports"
+        };
+        println!(
+            "fingerprint {}: {} {reports}",
+            bucket.fingerprint, bucket.count
+        );
+        println!("{}", bucket.example.trim_end());
+        println!();
+    }
+    ExitCode::SUCCESS
+}
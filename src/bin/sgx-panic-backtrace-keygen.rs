@@ -0,0 +1,69 @@
+//! Generate a key pair for encrypting panic reports.
+//!
+//! ```bash
+//! $ sgx-panic-backtrace-keygen panic-report.key
+//! ```
+
+use std::{env, fs, io::Write, process::ExitCode};
+
+use sgx_panic_backtrace::host::SecretKey;
+
+const USAGE: &str = "\
+usage: sgx-panic-backtrace-keygen <secret-key-file>
+
+Generates a new X25519 key pair for encrypting panic reports. Writes the secret
+key to <secret-key-file> and prints the public key to pass to the enclave's
+`PanicHookBuilder::encrypt_to`.
+
+Keep the secret key out of the enclave; it's only needed by
+`sgx-panic-backtrace-resolve --key`.";
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let key_path = match args.as_slice() {
+        [arg] if arg == "-h" || arg == "--help" => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        [key_path] => key_path,
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let secret_key = SecretKey::generate();
+
+    let mut options = fs::OpenOptions::new();
+    // don't clobber an existing key; any reports sealed to it would be lost.
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let result = options
+        .open(key_path)
+        .and_then(|mut file| writeln!(file, "{}", secret_key.to_hex()));
+    if let Err(err) = result {
+        eprintln!("error: failed to write secret key to '{key_path}': {err}");
+        return ExitCode::FAILURE;
+    }
+
+    let public_key = secret_key.public_key();
+    let public_key_hex = public_key
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    let public_key_array = public_key
+        .iter()
+        .map(|b| format!("0x{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    println!("wrote secret key to '{key_path}'");
+    println!();
+    println!("public key: {public_key_hex}");
+    println!();
+    println!("PanicHook::builder()");
+    println!("    .encrypt_to([{public_key_array}])");
+    println!("    .install();");
+    ExitCode::SUCCESS
+}
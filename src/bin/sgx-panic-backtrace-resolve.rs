@@ -0,0 +1,115 @@
+//! Symbolize the raw backtraces printed by an `sgx-panic-backtrace` panic hook.
+//!
+//! ```bash
+//! $ ftxsgx-runner <my-enclave-bin>.sgxs | sgx-panic-backtrace-resolve <my-enclave-bin>
+//! ```
+//!
+//! Pass `--key <secret-key-file>` to also decrypt the reports from a panic hook
+//! configured with `encrypt_to`, and `--store <dir>` to pick the enclave ELF for
+//! each backtrace from a symbol store (see `sgx-panic-backtrace-store`).
+
+use std::{env, fs, io, process::ExitCode};
+
+use sgx_panic_backtrace::host::{Resolver, SecretKey, SymbolStore, Symbolizer};
+
+const USAGE: &str = "\
+usage: sgx-panic-backtrace-resolve [--key <secret-key-file>] [--store <dir>] [<enclave-elf>]
+
+Reads enclave output on stdin and writes it to stdout, with the frames in each
+`stack backtrace:` block symbolized using the enclave ELF's debug info.
+
+With --store, the enclave ELF for each backtrace is looked up in the symbol
+store by its build id, falling back to <enclave-elf>, if given. One of the two
+is required.
+
+With --key, encrypted panic reports are decrypted with the secret key from
+`sgx-panic-backtrace-keygen` and symbolized too.";
+
+struct Args {
+    key_path: Option<String>,
+    store_dir: Option<String>,
+    elf_path: Option<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut key_path = None;
+        let mut store_dir = None;
+        let mut elf_path = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--key" => key_path = Some(args.next()?),
+                "--store" => store_dir = Some(args.next()?),
+                _ if arg.starts_with('-') || elf_path.is_some() => return None,
+                _ => elf_path = Some(arg),
+            }
+        }
+        if store_dir.is_none() && elf_path.is_none() {
+            return None;
+        }
+        Some(Self {
+            key_path,
+            store_dir,
+            elf_path,
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    if matches!(env::args().nth(1).as_deref(), Some("-h" | "--help")) {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let Some(args) = Args::parse(env::args().skip(1)) else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let secret_key = match &args.key_path {
+        None => None,
+        Some(key_path) => {
+            let secret_key = fs::read_to_string(key_path)
+                .ok()
+                .and_then(|hex| SecretKey::from_hex(&hex));
+            match secret_key {
+                Some(secret_key) => Some(secret_key),
+                None => {
+                    eprintln!("error: failed to read secret key from '{key_path}'");
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    };
+
+    let symbolizer = match &args.elf_path {
+        None => None,
+        Some(elf_path) => match Symbolizer::new(elf_path) {
+            Ok(symbolizer) => Some(symbolizer),
+            Err(err) => {
+                eprintln!("error: failed to load enclave ELF '{elf_path}': {err}");
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    let store = args.store_dir.map(SymbolStore::new);
+
+    let mut resolver = Resolver::new();
+    if let Some(symbolizer) = &symbolizer {
+        resolver = resolver.symbolizer(symbolizer);
+    }
+    if let Some(store) = &store {
+        resolver = resolver.store(store);
+    }
+    if let Some(secret_key) = &secret_key {
+        resolver = resolver.secret_key(secret_key);
+    }
+
+    match resolver.resolve(io::stdin().lock(), io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
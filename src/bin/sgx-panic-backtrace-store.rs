@@ -0,0 +1,55 @@
+//! Manage a symbol store of enclave ELF binaries, keyed by build id, for
+//! `sgx-panic-backtrace-resolve --store`.
+//!
+//! ```bash
+//! $ sgx-panic-backtrace-store add symbols target/x86_64-fortanix-unknown-sgx/release/my-enclave
+//! ```
+
+use std::{env, process::ExitCode};
+
+use sgx_panic_backtrace::host::SymbolStore;
+
+const USAGE: &str = "\
+usage: sgx-panic-backtrace-store add [--build-id <build-id>] <store-dir> <enclave-elf>...
+
+Copies each (unstripped) enclave ELF into the symbol store at
+<store-dir>/<build-id>/enclave.elf, keyed by its GNU build id.
+
+Enclaves that set their own build id (e.g. with `set_panic_hook!()`) don't have
+it in the ELF, so pass it in with --build-id.";
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let (build_id, store_dir, elf_paths) = match args.as_slice() {
+        [arg] if arg == "-h" || arg == "--help" => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        [cmd, flag, build_id, store_dir, elf_path] if cmd == "add" && flag == "--build-id" => (
+            Some(build_id.as_str()),
+            store_dir,
+            std::slice::from_ref(elf_path),
+        ),
+        [cmd, store_dir, elf_paths @ ..]
+            if cmd == "add" && !elf_paths.is_empty() && !store_dir.starts_with('-') =>
+        {
+            (None, store_dir, elf_paths)
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let store = SymbolStore::new(store_dir);
+    for elf_path in elf_paths {
+        match store.add(elf_path, build_id) {
+            Ok(path) => println!("added '{elf_path}' as '{}'", path.display()),
+            Err(err) => {
+                eprintln!("error: failed to add '{elf_path}': {err}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
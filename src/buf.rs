@@ -0,0 +1,100 @@
+//! Fixed-size buffers, so the panic hook can still write out a report when the
+//! enclave's heap is exhausted.
+
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+/// The size of the panic hook's report buffer. It lives on the stack, next to
+/// the hook's [`MAX_FRAMES`](crate::trace::MAX_FRAMES) captured frames (about
+/// 5 KiB), so it's sized for the header and the top of the backtrace, not the
+/// worst case. The rest of a long report streams out in more writes of up to
+/// this size, under [`lock::REPORTS`](crate::lock::REPORTS), so other reports
+/// don't interleave with it.
+pub(crate) const REPORT_BUF_SIZE: usize = 4 * 1024;
+
+/// Buffers everything written through it in a fixed array on the stack, then
+/// writes it all out to `out` with a single `write_all` on
+/// [`flush`](Write::flush). Like `BufWriter`, but it never allocates.
+///
+/// If the buffer fills up, it gets written out early, so nothing is lost.
+pub(crate) struct StackWriter<'a, W: Write + ?Sized, const N: usize> {
+    out: &'a mut W,
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<'a, W: Write + ?Sized, const N: usize> StackWriter<'a, W, N> {
+    pub(crate) fn new(out: &'a mut W) -> Self {
+        Self {
+            out,
+            buf: [0u8; N],
+            len: 0,
+        }
+    }
+
+    fn flush_buf(&mut self) -> io::Result<()> {
+        let len = self.len;
+        // reset even on error, so we don't write out the same bytes twice.
+        self.len = 0;
+        self.out.write_all(&self.buf[..len])
+    }
+}
+
+impl<W: Write + ?Sized, const N: usize> Write for StackWriter<'_, W, N> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        if self.len == N {
+            self.flush_buf()?;
+        }
+        let n = bytes.len().min(N - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf()?;
+        self.out.flush()
+    }
+}
+
+/// A string in a fixed-size buffer. Anything past `N` bytes gets truncated.
+pub(crate) struct FixedStr<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedStr<N> {
+    pub(crate) const EMPTY: Self = Self {
+        buf: [0u8; N],
+        len: 0,
+    };
+
+    pub(crate) fn set(&mut self, value: &dyn fmt::Display) {
+        self.len = 0;
+        // only fails if we ran out of room, which just truncates it.
+        let _ = fmt::Write::write_fmt(self, format_args!("{value}"));
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        // we only ever truncate on a char boundary.
+        std::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl<const N: usize> fmt::Write for FixedStr<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(N - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        if len < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Identifying the enclave build, so the host can pick the matching ELF binary
+//! to symbolize a backtrace with.
+
+use std::{fmt, sync::OnceLock};
+
+use crate::image;
+
+/// The line printed right before the `stack backtrace:` header that identifies
+/// the build, e.g. `build id: 2b4fd0b6d1e7c2a1`.
+pub(crate) const BUILD_ID_PREFIX: &str = "build id: ";
+
+static BUILD_ID: OnceLock<&'static str> = OnceLock::new();
+
+/// Set the build id printed with each backtrace. Only the first call has any
+/// effect.
+///
+/// Without one, the GNU build id (`.note.gnu.build-id`) of the main executable
+/// is used where we can find it (currently only on Linux, including library OSes
+/// like Gramine and Occlum). Inside SGX, set one with
+/// [`set_panic_hook!`](crate::set_panic_hook!) or [`build_id!`](crate::build_id!).
+pub fn set_build_id(build_id: &'static str) {
+    let _ = BUILD_ID.set(build_id);
+}
+
+/// The build id of the running binary, if we know it.
+#[derive(Clone, Copy)]
+pub(crate) enum BuildId {
+    /// Set with [`set_build_id`].
+    Custom(&'static str),
+    /// The contents of the main executable's `.note.gnu.build-id`.
+    Gnu(&'static [u8]),
+}
+
+impl BuildId {
+    pub(crate) fn get() -> Option<Self> {
+        match BUILD_ID.get() {
+            Some(build_id) => Some(Self::Custom(build_id)),
+            None => image::gnu_build_id().map(Self::Gnu),
+        }
+    }
+}
+
+impl fmt::Display for BuildId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Custom(build_id) => f.write_str(build_id),
+            Self::Gnu(build_id) => build_id.iter().try_for_each(|b| write!(f, "{b:02x}")),
+        }
+    }
+}
+
+/// A build id for the crate it's called from, fixed at compile time: the
+/// crate's name and version, e.g. `my-enclave-0.3.1`. Pass a string literal
+/// (e.g. a git SHA from your build script) to append it, as in
+/// `my-enclave-0.3.1+4f2a9c1`.
+///
+/// ```rust
+/// use sgx_panic_backtrace::build_id;
+///
+/// sgx_panic_backtrace::set_build_id(build_id!());
+/// ```
+///
+/// ```rust,ignore
+/// // with `println!("cargo:rustc-env=GIT_SHA={sha}")` in build.rs
+/// sgx_panic_backtrace::set_build_id(build_id!(env!("GIT_SHA")));
+/// ```
+#[macro_export]
+macro_rules! build_id {
+    () => {
+        ::std::concat!(
+            ::std::env!("CARGO_PKG_NAME"),
+            "-",
+            ::std::env!("CARGO_PKG_VERSION"),
+        )
+    };
+    ($suffix:expr) => {
+        ::std::concat!(
+            ::std::env!("CARGO_PKG_NAME"),
+            "-",
+            ::std::env!("CARGO_PKG_VERSION"),
+            "+",
+            $suffix,
+        )
+    };
+}
+
+/// [`set_panic_hook`](crate::set_panic_hook()), but also sets the
+/// [`build_id!`](crate::build_id!) of the calling crate, so the host can tell
+/// which build of the enclave panicked. Takes the same arguments as
+/// `build_id!`.
+///
+/// ```rust,no_run
+/// sgx_panic_backtrace::set_panic_hook!();
+/// ```
+#[macro_export]
+macro_rules! set_panic_hook {
+    ($($suffix:expr)?) => {{
+        $crate::set_build_id($crate::build_id!($($suffix)?));
+        $crate::set_panic_hook();
+    }};
+}
@@ -0,0 +1,364 @@
+//! [`catch_unwind_with_backtrace`] and [`spawn`]: catch a panic along with the
+//! backtrace the panic hook captured for it.
+//!
+//! By the time `catch_unwind` returns, the panicking stack is long gone, so the
+//! panic hook stashes the location and frames of each panic in a thread-local
+//! for us to pick up. It only does that inside one of our catch scopes, so other
+//! panics don't pay for the copy.
+//!
+//! Inside SGX, a thread-local's first use on each thread allocates, so the hook
+//! only touches the stash while some thread is inside a catch scope (see
+//! [`LIVE_SCOPES`]). Panics on an exhausted heap, outside any scope, never
+//! touch it.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt,
+    panic::{self, Location, UnwindSafe},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use crate::{
+    buf::FixedStr,
+    fnv::fnv1a,
+    trace::{DisplayFrames, Frame, MAX_FRAMES},
+    RawBacktrace, Redaction, FINGERPRINT_PREFIX,
+};
+
+/// Longer panic location file paths get truncated to this many bytes.
+const MAX_FILE_LEN: usize = 256;
+
+/// What the panic hook stashed for the most recent panic on this thread.
+struct Stash {
+    /// How many of our catch scopes this thread is in.
+    depth: usize,
+    /// Whether there's a panic stashed.
+    full: bool,
+    file: FixedStr<MAX_FILE_LEN>,
+    /// `line` and `column`, if the panic had a location.
+    line_column: Option<(u32, u32)>,
+    frames: [Frame; MAX_FRAMES],
+    num_frames: usize,
+    fingerprint: u64,
+    /// The panic hook's redaction, for displaying the [`CaughtPanic`].
+    redaction: Redaction,
+}
+
+/// How many of our catch scopes are alive, across all threads. If it's zero,
+/// no thread's stash is going to be picked up.
+static LIVE_SCOPES: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static STASH: RefCell<Stash> = const {
+        RefCell::new(Stash {
+            depth: 0,
+            full: false,
+            file: FixedStr::EMPTY,
+            line_column: None,
+            frames: [Frame::ZERO; MAX_FRAMES],
+            num_frames: 0,
+            fingerprint: 0,
+            redaction: Redaction::DEFAULT,
+        })
+    };
+}
+
+/// Called by the panic hook. Remember the panic's location, frames,
+/// fingerprint, and the hook's `redaction`, if we're inside [`catch_unwind_with_backtrace`]. Doesn't
+/// allocate, unless some thread is inside a catch scope and this thread has
+/// never touched its stash before.
+pub(crate) fn stash(
+    location: Option<&Location<'_>>,
+    frames: &[Frame],
+    fingerprint: u64,
+    redaction: Redaction,
+) {
+    if LIVE_SCOPES.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let _ = STASH.try_with(|stash| {
+        let Ok(mut stash) = stash.try_borrow_mut() else {
+            return;
+        };
+        if stash.depth == 0 {
+            return;
+        }
+        stash.full = true;
+        match location {
+            Some(location) => {
+                stash.file.set(&location.file());
+                stash.line_column = Some((location.line(), location.column()));
+            }
+            None => stash.line_column = None,
+        }
+        let num_frames = frames.len().min(MAX_FRAMES);
+        stash.frames[..num_frames].copy_from_slice(&frames[..num_frames]);
+        stash.num_frames = num_frames;
+        stash.fingerprint = fingerprint;
+        stash.redaction = redaction;
+    });
+}
+
+/// The panic message in `payload`, if it's a string.
+pub(crate) fn payload_str(payload: &(dyn Any + Send)) -> Option<&str> {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        Some(message)
+    } else {
+        payload.downcast_ref::<String>().map(String::as_str)
+    }
+}
+
+/// Marks this thread as inside a catch scope while it's alive.
+struct Scope;
+
+impl Scope {
+    fn enter() -> Self {
+        LIVE_SCOPES.fetch_add(1, Ordering::Release);
+        STASH.with(|stash| {
+            let mut stash = stash.borrow_mut();
+            stash.depth += 1;
+            // don't pick up a panic some inner `catch_unwind` already handled.
+            stash.full = false;
+        });
+        Self
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let _ = STASH.try_with(|stash| stash.borrow_mut().depth -= 1);
+        LIVE_SCOPES.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Take whatever the panic hook stashed for the panic we just caught.
+fn take_stashed(payload: Box<dyn Any + Send>) -> CaughtPanic {
+    let mut caught = CaughtPanic::unseen(payload);
+    STASH.with(|stash| {
+        let mut stash = stash.borrow_mut();
+        if !stash.full {
+            return;
+        }
+        stash.full = false;
+        caught.location = stash.line_column.map(|(line, column)| PanicLocation {
+            file: stash.file.as_str().to_owned(),
+            line,
+            column,
+        });
+        caught.backtrace = RawBacktrace::from_frames(stash.frames[..stash.num_frames].to_vec());
+        caught.fingerprint = Some(stash.fingerprint);
+        caught.redaction = stash.redaction;
+    });
+    caught
+}
+
+/// [`std::panic::catch_unwind`], but a caught panic comes back with its location
+/// and the backtrace the panic hook captured, not just the payload.
+///
+/// ```rust
+/// sgx_panic_backtrace::set_panic_hook();
+///
+/// let result = sgx_panic_backtrace::catch_unwind_with_backtrace(|| {
+///     panic!("bad request");
+/// });
+/// let caught = result.unwrap_err();
+/// assert_eq!(caught.message(), Some("bad request"));
+/// assert!(caught.location().is_some());
+/// eprintln!("{caught}");
+/// ```
+///
+/// The location and backtrace come from the panic hook, so they're only there
+/// if it's installed (with [`set_panic_hook`](crate::set_panic_hook()) or
+/// [`PanicHook::builder`](crate::PanicHook::builder)). The hook still reports
+/// the panic as usual. A payload re-raised with
+/// [`resume_unwind`](std::panic::resume_unwind) gets the location and backtrace
+/// of the last panic on this thread inside the scope, if any.
+pub fn catch_unwind_with_backtrace<F, R>(f: F) -> Result<R, CaughtPanic>
+where
+    F: FnOnce() -> R + UnwindSafe,
+{
+    let scope = Scope::enter();
+    let result = panic::catch_unwind(f);
+    drop(scope);
+    result.map_err(take_stashed)
+}
+
+/// [`std::thread::spawn`], but [`join`](JoinHandle::join)ing a thread that
+/// panicked returns a [`CaughtPanic`], with the panic's location and backtrace.
+///
+/// ```rust
+/// sgx_panic_backtrace::set_panic_hook();
+///
+/// let handle = sgx_panic_backtrace::spawn(|| panic!("worker died"));
+/// let caught = handle.join().unwrap_err();
+/// assert_eq!(caught.message(), Some("worker died"));
+/// ```
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    // the closure's state doesn't outlive the thread, just like with
+    // `thread::spawn`.
+    JoinHandle(thread::spawn(move || {
+        catch_unwind_with_backtrace(panic::AssertUnwindSafe(f))
+    }))
+}
+
+/// An owned permission to join a thread started with [`spawn`].
+#[derive(Debug)]
+pub struct JoinHandle<T>(thread::JoinHandle<Result<T, CaughtPanic>>);
+
+impl<T> JoinHandle<T> {
+    /// Wait for the thread to finish. If it panicked, returns the caught panic.
+    pub fn join(self) -> Result<T, CaughtPanic> {
+        match self.0.join() {
+            Ok(result) => result,
+            // shouldn't happen, since we catch the panic on the thread.
+            Err(payload) => Err(CaughtPanic::unseen(payload)),
+        }
+    }
+
+    /// The thread's handle.
+    pub fn thread(&self) -> &thread::Thread {
+        self.0.thread()
+    }
+
+    /// Whether the thread has finished running.
+    pub fn is_finished(&self) -> bool {
+        self.0.is_finished()
+    }
+}
+
+/// A panic caught by [`catch_unwind_with_backtrace`] or a [`spawn`]ed thread:
+/// the panic payload, plus the location and backtrace the panic hook captured.
+///
+/// Its [`Display`](fmt::Display) output looks like the panic hook's text
+/// report, so the backtrace can be symbolized the same way. It holds back the
+/// panic message just like the report does, according to the hook's
+/// [`Redaction`] (or [`Redaction::DEFAULT`], if the hook didn't see the panic).
+/// The message itself is still there in [`message`](Self::message) and
+/// [`payload`](Self::payload).
+pub struct CaughtPanic {
+    payload: Box<dyn Any + Send>,
+    location: Option<PanicLocation>,
+    backtrace: RawBacktrace,
+    fingerprint: Option<u64>,
+    redaction: Redaction,
+}
+
+impl CaughtPanic {
+    /// A panic the panic hook didn't see, with nothing but its payload.
+    fn unseen(payload: Box<dyn Any + Send>) -> Self {
+        Self {
+            payload,
+            location: None,
+            backtrace: RawBacktrace::from_frames(Vec::new()),
+            fingerprint: None,
+            redaction: Redaction::DEFAULT,
+        }
+    }
+
+    /// The panic message, if the payload is a string (i.e. the panic came from
+    /// `panic!` with a message).
+    pub fn message(&self) -> Option<&str> {
+        payload_str(&*self.payload)
+    }
+
+    /// The panic payload.
+    pub fn payload(&self) -> &(dyn Any + Send) {
+        &*self.payload
+    }
+
+    /// The panic payload, e.g. to pass to [`std::panic::resume_unwind`].
+    pub fn into_payload(self) -> Box<dyn Any + Send> {
+        self.payload
+    }
+
+    /// Where the panic happened, if the panic hook saw it.
+    pub fn location(&self) -> Option<&PanicLocation> {
+        self.location.as_ref()
+    }
+
+    /// The raw backtrace of the panic. Empty if the panic hook didn't see it.
+    pub fn backtrace(&self) -> &RawBacktrace {
+        &self.backtrace
+    }
+
+    /// The panic's crash fingerprint, if the panic hook saw it. See
+    /// [`PanicHook`](crate::PanicHook#crash-fingerprints).
+    pub fn fingerprint(&self) -> Option<u64> {
+        self.fingerprint
+    }
+}
+
+impl fmt::Display for CaughtPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("panicked")?;
+        if let Some(location) = &self.location {
+            write!(f, " at {location}")?;
+        }
+        match (self.redaction, self.message()) {
+            (Redaction::Full, Some(message)) => writeln!(f, ":\n{message}")?,
+            (Redaction::HashedMessage, Some(message)) => writeln!(
+                f,
+                ":\n[redacted message {:016x}]",
+                fnv1a(message.as_bytes())
+            )?,
+            _ => writeln!(f)?,
+        }
+        if let Some(fingerprint) = self.fingerprint {
+            writeln!(f, "{FINGERPRINT_PREFIX}{fingerprint:016x}")?;
+        }
+        DisplayFrames(self.backtrace.frames()).fmt(f)
+    }
+}
+
+impl fmt::Debug for CaughtPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self.redaction {
+            Redaction::Full => self.message(),
+            Redaction::HashedMessage | Redaction::LocationOnly => None,
+        };
+        f.debug_struct("CaughtPanic")
+            .field("message", &message)
+            .field("location", &self.location)
+            .field("backtrace", &self.backtrace)
+            .field("fingerprint", &self.fingerprint)
+            .field("redaction", &self.redaction)
+            .finish()
+    }
+}
+
+/// An owned copy of a panic's [`Location`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PanicLocation {
+    file: String,
+    line: u32,
+    column: u32,
+}
+
+impl PanicLocation {
+    /// The source file the panic came from.
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    /// The line the panic came from.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// The column the panic came from.
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+}
+
+impl fmt::Display for PanicLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
@@ -0,0 +1,301 @@
+//! Per-thread context and breadcrumbs, printed with the panic report so we know
+//! what the enclave was doing when it panicked, not just where.
+//!
+//! Everything lives in fixed-size thread-local buffers, so the panic hook can
+//! read it back safely. Inside SGX, a thread-local's first use on each thread
+//! allocates, so the hook only reads it while some thread has recorded context
+//! (see [`THREADS_WITH_CONTEXT`]).
+
+use std::{
+    cell::RefCell,
+    fmt::{self, Write},
+    marker::PhantomData,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{buf::FixedStr, json::JsonStr};
+
+/// The max number of nested contexts we'll remember. Deeper ones still nest
+/// correctly, they just don't show up in the report.
+const MAX_CONTEXT_DEPTH: usize = 8;
+
+/// The number of most recent breadcrumbs we'll remember.
+const MAX_BREADCRUMBS: usize = 16;
+
+/// Longer contexts and breadcrumbs get truncated to this many bytes.
+const MAX_LEN: usize = 96;
+
+struct Context {
+    stack: [FixedStr<MAX_LEN>; MAX_CONTEXT_DEPTH],
+    /// Can be more than [`MAX_CONTEXT_DEPTH`].
+    depth: usize,
+    breadcrumbs: [FixedStr<MAX_LEN>; MAX_BREADCRUMBS],
+    /// The total number of breadcrumbs ever left on this thread. The most
+    /// recent one is at `num_breadcrumbs - 1` (mod [`MAX_BREADCRUMBS`]).
+    num_breadcrumbs: usize,
+    /// Whether this thread counts towards [`THREADS_WITH_CONTEXT`].
+    counted: bool,
+}
+
+impl Context {
+    const fn new() -> Self {
+        Self {
+            stack: [FixedStr::EMPTY; MAX_CONTEXT_DEPTH],
+            depth: 0,
+            breadcrumbs: [FixedStr::EMPTY; MAX_BREADCRUMBS],
+            num_breadcrumbs: 0,
+            counted: false,
+        }
+    }
+
+    /// Count this thread towards [`THREADS_WITH_CONTEXT`], if it isn't
+    /// already. Call before recording anything.
+    fn count(&mut self) {
+        if !self.counted {
+            self.counted = true;
+            THREADS_WITH_CONTEXT.fetch_add(1, Ordering::Release);
+        }
+    }
+
+    fn contexts(&self) -> impl Iterator<Item = &str> {
+        self.stack[..self.depth.min(MAX_CONTEXT_DEPTH)]
+            .iter()
+            .map(FixedStr::as_str)
+    }
+
+    /// The remembered breadcrumbs, oldest first.
+    fn breadcrumbs(&self) -> impl Iterator<Item = &str> {
+        let start = self.num_breadcrumbs.saturating_sub(MAX_BREADCRUMBS);
+        (start..self.num_breadcrumbs).map(|idx| self.breadcrumbs[idx % MAX_BREADCRUMBS].as_str())
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        if self.counted {
+            THREADS_WITH_CONTEXT.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
+
+/// How many threads have recorded any context or breadcrumbs. If it's zero,
+/// there's nothing for the panic hook to read.
+static THREADS_WITH_CONTEXT: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static CONTEXT: RefCell<Context> = const { RefCell::new(Context::new()) };
+}
+
+/// Run `f` with `context` describing what this thread is doing. If `f` panics,
+/// the panic report lists it, along with any enclosing contexts.
+///
+/// ```rust
+/// use sgx_panic_backtrace::with_context;
+///
+/// let tenant_id = 42;
+/// with_context(format_args!("handling attestation for {tenant_id}"), || {
+///     // ...
+/// });
+/// ```
+///
+/// Contexts longer than 96 bytes get truncated, and only the 8 outermost
+/// contexts are printed.
+pub fn with_context<R>(context: impl fmt::Display, f: impl FnOnce() -> R) -> R {
+    let _guard = push_context(context);
+    f()
+}
+
+/// Push `context` onto this thread's context stack until the returned guard is
+/// dropped. See [`with_context`].
+pub fn push_context(context: impl fmt::Display) -> ContextGuard {
+    let depth = CONTEXT.with(|cx| {
+        let mut cx = cx.borrow_mut();
+        cx.count();
+        let depth = cx.depth;
+        if let Some(slot) = cx.stack.get_mut(depth) {
+            slot.set(&context);
+        }
+        cx.depth += 1;
+        depth
+    });
+    ContextGuard {
+        depth,
+        _not_send: PhantomData,
+    }
+}
+
+/// Pops its context off this thread's context stack when dropped. See
+/// [`push_context`].
+#[must_use = "the context is popped as soon as the guard is dropped"]
+pub struct ContextGuard {
+    depth: usize,
+    // the context stack is per thread.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        // also pops any inner contexts whose guards were leaked.
+        let _ = CONTEXT.try_with(|cx| cx.borrow_mut().depth = self.depth);
+    }
+}
+
+impl fmt::Debug for ContextGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContextGuard")
+            .field("depth", &self.depth)
+            .finish()
+    }
+}
+
+/// Leave a breadcrumb on this thread. The panic report lists the 16 most recent
+/// ones, oldest first. See also [`breadcrumb!`](crate::breadcrumb!), which takes
+/// a format string.
+///
+/// ```rust
+/// sgx_panic_backtrace::breadcrumb("loaded sealed config");
+/// ```
+pub fn breadcrumb(breadcrumb: impl fmt::Display) {
+    CONTEXT.with(|cx| {
+        let mut cx = cx.borrow_mut();
+        cx.count();
+        let idx = cx.num_breadcrumbs % MAX_BREADCRUMBS;
+        cx.breadcrumbs[idx].set(&breadcrumb);
+        cx.num_breadcrumbs += 1;
+    });
+}
+
+/// Leave a breadcrumb on this thread. Takes the same arguments as [`format!`].
+/// See [`breadcrumb`](crate::breadcrumb()).
+///
+/// ```rust
+/// let request_id = 7;
+/// sgx_panic_backtrace::breadcrumb!("decrypted request {request_id}");
+/// ```
+#[macro_export]
+macro_rules! breadcrumb {
+    ($($arg:tt)+) => {
+        $crate::breadcrumb(::std::format_args!($($arg)+))
+    };
+}
+
+/// Call `f` with the current thread's context, unless it's being modified right
+/// now (i.e. we panicked in the middle of recording context) or is already gone.
+/// Doesn't touch the thread-local at all if no thread has recorded context.
+fn with_current<F: FnOnce(&Context) -> fmt::Result>(f: F) -> fmt::Result {
+    if THREADS_WITH_CONTEXT.load(Ordering::Acquire) == 0 {
+        return Ok(());
+    }
+    CONTEXT
+        .try_with(|cx| match cx.try_borrow() {
+            Ok(cx) => f(&cx),
+            Err(_) => Ok(()),
+        })
+        .unwrap_or(Ok(()))
+}
+
+/// Displays a context or breadcrumb with any control characters escaped, e.g.
+/// `\n`, so each entry stays on its own line of the text panic report and can't
+/// pass for a line of the report itself (e.g. `stack backtrace:`).
+struct EscapeControl<'a>(&'a str);
+
+impl fmt::Display for EscapeControl<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            if c.is_control() {
+                write!(f, "{}", c.escape_default())?;
+            } else {
+                f.write_char(c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Displays the current thread's `context:` and `breadcrumbs:` sections for the
+/// text panic report, if there are any.
+pub(crate) struct DisplayContext;
+
+impl fmt::Display for DisplayContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        with_current(|cx| {
+            let sections: [(&str, &mut dyn Iterator<Item = &str>); 2] = [
+                ("context:", &mut cx.contexts()),
+                ("breadcrumbs:", &mut cx.breadcrumbs()),
+            ];
+            for (header, entries) in sections {
+                let mut entries = entries.peekable();
+                if entries.peek().is_some() {
+                    writeln!(f, "{header}")?;
+                }
+                for entry in entries {
+                    writeln!(f, "  - {}", EscapeControl(entry))?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Displays the current thread's context and breadcrumbs as the
+/// `,"context":[..],"breadcrumbs":[..]` fields of a JSON panic report.
+pub(crate) struct JsonContext;
+
+impl fmt::Display for JsonContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_array<'a>(
+            f: &mut fmt::Formatter<'_>,
+            key: &str,
+            entries: impl Iterator<Item = &'a str>,
+        ) -> fmt::Result {
+            write!(f, ",\"{key}\":[")?;
+            for (idx, entry) in entries.enumerate() {
+                if idx > 0 {
+                    f.write_char(',')?;
+                }
+                JsonStr(entry).fmt(f)?;
+            }
+            f.write_char(']')
+        }
+
+        let mut written = false;
+        with_current(|cx| {
+            written = true;
+            write_array(f, "context", cx.contexts())?;
+            write_array(f, "breadcrumbs", cx.breadcrumbs())
+        })?;
+        if !written {
+            f.write_str(",\"context\":[],\"breadcrumbs\":[]")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_entries_stay_on_one_line() {
+        with_context("request\nstack backtrace:\n   0: 0x10", || {
+            breadcrumb("with newline\r\n\tand tab \u{1b}[31m");
+            breadcrumb("unicode is fine: ünïcødé");
+
+            assert_eq!(
+                DisplayContext.to_string(),
+                "context:\n\
+                 \x20 - request\\nstack backtrace:\\n   0: 0x10\n\
+                 breadcrumbs:\n\
+                 \x20 - with newline\\r\\n\\tand tab \\u{1b}[31m\n\
+                 \x20 - unicode is fine: ünïcødé\n",
+            );
+            // JSON has its own escaping
+            assert_eq!(
+                JsonContext.to_string(),
+                ",\"context\":[\"request\\nstack backtrace:\\n   0: 0x10\"],\
+                 \"breadcrumbs\":[\"with newline\\r\\n\\tand tab \\u001b[31m\",\
+                 \"unicode is fine: ünïcødé\"]",
+            );
+        });
+    }
+}
@@ -0,0 +1,115 @@
+//! A frame pointer walker, for when the unwinder gives up early.
+//!
+//! libunwind needs complete `.eh_frame` info for every function on the stack,
+//! and inside SGX it's common to get a trace that stops after a frame or two.
+//! If the enclave is built with `-C force-frame-pointers=yes`, each frame
+//! starts with the caller's saved `rbp` and then the return address, so we can
+//! follow that chain instead.
+//!
+//! The chain might be garbage (e.g. a function built without frame pointers
+//! used `rbp` as a general purpose register), so before reading through any
+//! `rbp`, we check that it's aligned, inside the stack, and above the previous
+//! one. The walk also stops at the first return address that's not inside a
+//! loaded image.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::{trace::Frame, unwinder};
+
+/// When to walk the frame pointer chain instead of running the unwinder.
+/// Only supported on `x86_64`; elsewhere, this is always
+/// [`Never`](FramePointers::Never). Without any `unwinder-*` cargo feature,
+/// there's no unwinder to run, so it's always [`Always`](FramePointers::Always).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FramePointers {
+    /// Only ever use the unwinder.
+    Never,
+    /// If the unwinder gives up after a frame or two, also walk the frame
+    /// pointer chain, and use it instead if it finds more frames. Without frame
+    /// pointers, the chain ends almost right away, so the unwinder wins.
+    #[default]
+    Fallback,
+    /// Only ever walk the frame pointer chain.
+    Always,
+}
+
+static FRAME_POINTERS: AtomicU8 = AtomicU8::new(FramePointers::Fallback as u8);
+
+/// Set when backtraces walk the frame pointer chain. Defaults to
+/// [`FramePointers::Fallback`].
+///
+/// ```rust
+/// use sgx_panic_backtrace::FramePointers;
+///
+/// // our `.eh_frame` is hopeless; we build with `-C force-frame-pointers=yes`
+/// sgx_panic_backtrace::set_frame_pointers(FramePointers::Always);
+/// ```
+pub fn set_frame_pointers(frame_pointers: FramePointers) {
+    FRAME_POINTERS.store(frame_pointers as u8, Ordering::Relaxed);
+}
+
+/// Get when backtraces walk the frame pointer chain. See [`set_frame_pointers`].
+pub fn get_frame_pointers() -> FramePointers {
+    if !cfg!(target_arch = "x86_64") {
+        return FramePointers::Never;
+    }
+    if !unwinder::HAS_UNWINDER {
+        return FramePointers::Always;
+    }
+    match FRAME_POINTERS.load(Ordering::Relaxed) {
+        x if x == FramePointers::Never as u8 => FramePointers::Never,
+        x if x == FramePointers::Always as u8 => FramePointers::Always,
+        _ => FramePointers::Fallback,
+    }
+}
+
+/// Walk the frame pointer chain, starting from our caller, calling `f` on each
+/// frame until it returns `false`.
+///
+/// Doesn't allocate (except maybe to find the main thread's stack on Linux).
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+pub(crate) fn walk(f: &mut dyn FnMut(Frame) -> bool) {
+    use std::{arch::asm, mem};
+
+    use crate::image;
+
+    let Some(stack) = image::stack_bounds() else {
+        return;
+    };
+
+    let mut fp: usize;
+    unsafe { asm!("mov {}, rbp", out(reg) fp, options(nomem, nostack, preserves_flags)) };
+
+    // the stack grows down, so each caller's frame is above the last one.
+    let mut prev_fp = 0;
+    while fp % mem::align_of::<usize>() == 0
+        && fp > prev_fp
+        && fp >= stack.start
+        && fp.saturating_add(2 * mem::size_of::<usize>()) <= stack.end
+    {
+        // SAFETY: we just checked both words are aligned and inside the stack.
+        let (next_fp, return_address) = unsafe {
+            let fp = fp as *const usize;
+            (*fp, *fp.add(1))
+        };
+        if image::module_base(return_address).is_none() {
+            break;
+        }
+        // the caller's stack pointer was right above the return address.
+        let sp = fp + 2 * mem::size_of::<usize>();
+        let frame = Frame::new(
+            return_address,
+            sp,
+            unwinder::enclosing_function(return_address),
+        );
+        if !f(frame) {
+            break;
+        }
+        prev_fp = fp;
+        fp = next_fp;
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) fn walk(_f: &mut dyn FnMut(Frame) -> bool) {}
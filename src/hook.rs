@@ -0,0 +1,776 @@
+//! The configurable panic hook. See [`PanicHook::builder`].
+
+use std::{
+    fmt,
+    io::{self, Write},
+    panic::{self, Location, PanicHookInfo},
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    thread,
+};
+
+#[cfg(feature = "encrypt")]
+use crypto_box::{aead::OsRng, PublicKey};
+
+#[cfg(feature = "encrypt")]
+use crate::armor;
+use crate::{
+    buf::{StackWriter, REPORT_BUF_SIZE},
+    build_id::BuildId,
+    catch,
+    context::{DisplayContext, JsonContext},
+    fnv::{fnv1a, Fnv1a},
+    get_backtrace_style,
+    json::{self, JsonFrames, JsonOptDisplay, JsonOptStr, JsonStr},
+    layout::{DisplayMemoryLayout, JsonMemoryLayout, MemoryLayout},
+    lock, set_backtrace_style,
+    trace::{self, DisplayFrames, Frame, MAX_FRAMES},
+    BacktraceStyle, FINGERPRINT_PREFIX,
+};
+
+/// How many of the top frames (below the panic call site) go into the
+/// [`fingerprint`]. Enough to tell apart different paths to the same panic
+/// location, but not so many that unrelated differences deep in the stack split
+/// up the same crash.
+const FINGERPRINT_FRAMES: usize = 8;
+
+type PrevHook = Box<dyn Fn(&PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// A stable fingerprint of a panic, for grouping the same crash across enclave
+/// instances: a hash of the panic location and the top few frame offsets,
+/// excluding the panic hook's frames. The offsets are image-relative, so the
+/// fingerprint doesn't depend on where the enclave happened to be loaded.
+fn fingerprint(location: Option<&Location<'_>>, frames: &[Frame]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    if let Some(location) = location {
+        hasher.write(location.file().as_bytes());
+        hasher.write(&location.line().to_le_bytes());
+        hasher.write(&location.column().to_le_bytes());
+    }
+    for frame in frames.iter().take(FINGERPRINT_FRAMES) {
+        hasher.write(&(frame.offset() as u64).to_le_bytes());
+    }
+    hasher.finish()
+}
+
+/// Where the panic hook writes its reports.
+#[derive(Default)]
+#[non_exhaustive]
+pub enum Output {
+    /// The enclave's stdout.
+    #[default]
+    Stdout,
+    /// The enclave's stderr.
+    Stderr,
+    /// Any other sink, e.g. a `TcpStream` to a host-side collector or an
+    /// in-memory buffer in tests. Flushed after each report, just like stdout.
+    ///
+    /// If the writer panics, std aborts the process right away (a panic inside
+    /// a panic hook), and the report is lost. See
+    /// [`PanicHookBuilder::stderr_notice`] to get the gist out first.
+    ///
+    /// ```rust,no_run
+    /// use sgx_panic_backtrace::{Output, PanicHook};
+    /// use std::net::TcpStream;
+    ///
+    /// let stream = TcpStream::connect("panic-collector:9000").unwrap();
+    /// PanicHook::builder()
+    ///     .output(Output::Custom(Box::new(stream)))
+    ///     .install();
+    /// ```
+    Custom(Box<dyn Write + Send>),
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdout => f.write_str("Stdout"),
+            Self::Stderr => f.write_str("Stderr"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// The panic hook's [`Output`]. Custom sinks need a lock since the hook only
+/// gets a shared reference.
+enum Sink {
+    Stdout,
+    Stderr,
+    Custom(Mutex<Box<dyn Write + Send>>),
+}
+
+/// The sink of the most recently installed hook, so
+/// [`print_backtrace`](crate::print_backtrace) writes to the same place.
+static INSTALLED_SINK: RwLock<Option<Arc<Sink>>> = RwLock::new(None);
+
+/// Call `f` with the installed hook's [`Output`], or stdout if there isn't one.
+pub(crate) fn with_installed_output(
+    f: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let sink = INSTALLED_SINK
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    match sink.as_deref() {
+        None | Some(Sink::Stdout) => f(&mut io::stdout().lock()),
+        Some(Sink::Stderr) => f(&mut io::stderr().lock()),
+        Some(Sink::Custom(writer)) => {
+            let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+            f(&mut *writer)
+        }
+    }
+}
+
+/// The format of the panic reports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReportFormat {
+    /// The human-readable `enclave panic:` header followed by the
+    /// `stack backtrace:` frame offsets.
+    #[default]
+    Text,
+    /// One JSON object per line, so log pipelines can ingest the reports
+    /// directly. Panic messages with newlines stay on one line.
+    ///
+    /// ```json
+    /// {"schema":1,"type":"panic","message":"foo","message_hash":null,"location":{"file":"bar.rs","line":10,"column":5},"thread":"main","build_id":"my-enclave-0.3.1","fingerprint":"5f0c2e8a9d41b7e3","context":[],"breadcrumbs":[],"memory_layout":null,"frames":[{"offset":"0x1b09d9"},{"offset":"0x29d90","module":"/lib/x86_64-linux-gnu/libc.so.6"}]}
+    /// ```
+    ///
+    /// `message`, `message_hash`, `location`, `thread`, and `build_id` may be
+    /// `null`. So may `memory_layout`, unless it was turned on with
+    /// [`PanicHookBuilder::memory_layout`]. Each frame has its hex `offset`,
+    /// plus a `module` path if it's in a shared library (only on Linux), or
+    /// `"in_image":false` if its ip isn't inside any loaded image.
+    /// `schema` only changes if an existing field changes meaning or gets
+    /// removed.
+    Json,
+}
+
+/// How much of the panic message goes into the report.
+///
+/// Panic messages can contain anything that was formatted into them, e.g. key
+/// material or user data from an `expect()` on a secret value. Everything the
+/// hook prints goes to the untrusted host, so release builds only print the
+/// panic location by default.
+///
+/// The default is picked at compile time. Enable one of the
+/// `redact-location-only`, `redact-hashed-message`, or `redact-none` cargo
+/// features to choose it explicitly (the most restrictive one wins). Otherwise,
+/// debug builds default to [`Full`](Self::Full) and release builds default to
+/// [`LocationOnly`](Self::LocationOnly).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Redaction {
+    /// Only print the panic location.
+    LocationOnly,
+    /// Print the panic location and a (non-cryptographic) 64-bit FNV-1a hash of
+    /// the panic message. Identical panics can still be told apart from
+    /// different ones, without revealing the message.
+    ///
+    /// The hash doesn't hide low-entropy messages from a brute-force search,
+    /// so prefer [`LocationOnly`](Self::LocationOnly) if that matters.
+    HashedMessage,
+    /// Print the full panic message.
+    Full,
+}
+
+impl Redaction {
+    /// The compile-time default. See [`Redaction`].
+    pub const DEFAULT: Self = if cfg!(feature = "redact-location-only") {
+        Self::LocationOnly
+    } else if cfg!(feature = "redact-hashed-message") {
+        Self::HashedMessage
+    } else if cfg!(feature = "redact-none") || cfg!(debug_assertions) {
+        Self::Full
+    } else {
+        Self::LocationOnly
+    };
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A panic hook that prints out the panic and raw backtrace addresses when the
+/// enclave panics.
+///
+/// Each report is formatted into a fixed buffer on the stack and streamed out a
+/// few KiB at a time, so panics from an exhausted heap still get reported. The
+/// hook doesn't allocate otherwise either, except for:
+///
+/// + [Encrypted](PanicHookBuilder::encrypt_to) reports, which need the heap to
+///   seal the report.
+/// + Thread-locals. SGX has no native thread-locals, so each one allocates the
+///   first time a thread touches it. The hook only touches the
+///   [`catch_unwind_with_backtrace`](crate::catch_unwind_with_backtrace) stash
+///   while some thread is inside it, and the [context](crate::with_context)
+///   while some thread has recorded any.
+/// + On Linux, looking up the main thread's stack (for the frame pointer walk
+///   and the [memory layout](PanicHookBuilder::memory_layout)), which mallocs.
+///   If the heap is exhausted, that lookup just fails, and the report goes out
+///   without it.
+///
+/// Instead, the hook needs about 10 KiB of the panicking thread's stack: 5 KiB
+/// for the captured frames and 4 KiB for the report buffer, plus whatever the
+/// unwinder and the output need. The same goes for a
+/// [`TracingAllocator`](crate::TracingAllocator) reporting a failed
+/// allocation.
+///
+/// ```rust,no_run
+/// use sgx_panic_backtrace::{Output, PanicHook};
+///
+/// PanicHook::builder()
+///     .output(Output::Stderr)
+///     .frame_limit(32)
+///     .install();
+/// ```
+///
+/// # Crash fingerprints
+///
+/// Each report includes a `fingerprint:` line (or JSON field): a hash of the
+/// panic location and the top few frame offsets. The same crash gets the same
+/// fingerprint in every instance of the same enclave build, so reports can be
+/// grouped with `sgx-panic-backtrace-buckets`.
+///
+/// # Context
+///
+/// Any [`with_context`](crate::with_context) scopes and recent
+/// [`breadcrumb`](crate::breadcrumb())s of the panicking thread get listed under
+/// the backtrace (or in the `context` and `breadcrumbs` JSON fields), one per
+/// line, with any control characters escaped. They're printed regardless of
+/// the [`Redaction`], so keep secrets out of them.
+pub struct PanicHook {
+    sink: Arc<Sink>,
+    frame_limit: usize,
+    redaction: Redaction,
+    format: ReportFormat,
+    memory_layout: bool,
+    stderr_notice: bool,
+    #[cfg(feature = "encrypt")]
+    encrypt_to: Option<PublicKey>,
+    prev_hook: Option<PrevHook>,
+}
+
+/// Configures a [`PanicHook`]. Finish with [`install`](Self::install).
+#[must_use]
+pub struct PanicHookBuilder {
+    output: Output,
+    frame_limit: usize,
+    chain_prev_hook: Option<bool>,
+    redaction: Option<Redaction>,
+    format: ReportFormat,
+    memory_layout: bool,
+    stderr_notice: bool,
+    #[cfg(feature = "encrypt")]
+    encrypt_to: Option<PublicKey>,
+}
+
+impl PanicHook {
+    /// Start configuring a new panic hook. The defaults match
+    /// [`set_panic_hook`](crate::set_panic_hook).
+    pub fn builder() -> PanicHookBuilder {
+        PanicHookBuilder {
+            output: Output::Stdout,
+            frame_limit: MAX_FRAMES,
+            chain_prev_hook: None,
+            redaction: None,
+            format: ReportFormat::Text,
+            memory_layout: false,
+            stderr_notice: false,
+            #[cfg(feature = "encrypt")]
+            encrypt_to: None,
+        }
+    }
+
+    fn call(&self, panic_info: &PanicHookInfo<'_>) {
+        // keep holding the report lock, so the previous hook's output doesn't
+        // interleave with other reports either.
+        let _guard = self.report(panic_info);
+
+        // continue the default panic behaviour.
+        if let Some(prev_hook) = &self.prev_hook {
+            prev_hook(panic_info);
+        }
+    }
+
+    /// Trace the stack and write out the report. Returns the
+    /// [`lock::REPORTS`] guard it wrote the report under.
+    #[inline(never)]
+    fn report(&self, panic_info: &PanicHookInfo<'_>) -> lock::Guard<'static> {
+        let mut frames = [Frame::default(); MAX_FRAMES];
+        let num_frames = trace::capture_frames(&mut frames);
+        let all_frames = &frames[..num_frames];
+        let trimmed_frames =
+            trace::trim_panic_frames(all_frames, Self::report as *const () as usize);
+        let fingerprint = fingerprint(panic_info.location(), trimmed_frames);
+        let frames = match get_backtrace_style() {
+            // still captured above for the fingerprint.
+            BacktraceStyle::Off => &[],
+            BacktraceStyle::Short => trimmed_frames,
+            BacktraceStyle::Full => all_frames,
+        };
+        catch::stash(panic_info.location(), frames, fingerprint, self.redaction);
+        let frames = &frames[..frames.len().min(self.frame_limit)];
+        let layout = self
+            .memory_layout
+            .then(|| MemoryLayout::current(trimmed_frames.first().map_or(0, Frame::sp)));
+        let layout = layout.as_ref();
+
+        // threads panicking at the same time take turns writing their reports,
+        // but only once they're done tracing, so a slow sink doesn't hold up
+        // other threads' backtraces.
+        let guard = lock::REPORTS.lock();
+        // ignore any errors so we don't double panic. the enclave's about to
+        // abort anyway.
+        let _ = match &*self.sink {
+            Sink::Stdout => self.write_report(
+                &mut io::stdout().lock(),
+                panic_info,
+                frames,
+                fingerprint,
+                layout,
+            ),
+            Sink::Stderr => self.write_report(
+                &mut io::stderr().lock(),
+                panic_info,
+                frames,
+                fingerprint,
+                layout,
+            ),
+            Sink::Custom(writer) => {
+                // the writer is user code, and if it panics, std aborts on the
+                // spot. get the gist out somewhere it can't be lost first.
+                if self.stderr_notice {
+                    let _ = self.write_notice(
+                        &mut io::stderr().lock(),
+                        panic_info.location(),
+                        fingerprint,
+                    );
+                }
+                // a previous report panicking while holding the lock doesn't
+                // make the writer any less usable.
+                let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+                self.write_report(&mut *writer, panic_info, frames, fingerprint, layout)
+            }
+        };
+        guard
+    }
+
+    /// The one line we print to stderr before handing the report to an
+    /// [`Output::Custom`] writer. See [`PanicHookBuilder::stderr_notice`].
+    fn write_notice<W: Write>(
+        &self,
+        out: &mut W,
+        location: Option<&Location<'_>>,
+        fingerprint: u64,
+    ) -> io::Result<()> {
+        #[cfg(feature = "encrypt")]
+        let encrypted = self.encrypt_to.is_some();
+        #[cfg(not(feature = "encrypt"))]
+        let encrypted = false;
+
+        // if it's encrypted, the location would give away what we're trying to
+        // hide.
+        match self.format {
+            ReportFormat::Text if encrypted => {
+                writeln!(out, "enclave panic: panicked; writing the encrypted report")?;
+            }
+            ReportFormat::Text => {
+                write!(out, "enclave panic: panicked")?;
+                if let Some(location) = location {
+                    write!(out, " at {location}")?;
+                }
+                writeln!(
+                    out,
+                    " ({FINGERPRINT_PREFIX}{fingerprint:016x}); writing the report"
+                )?;
+            }
+            ReportFormat::Json => {
+                write!(
+                    out,
+                    "{{\"schema\":{},\"type\":\"panic_notice\",\"encrypted\":{encrypted}",
+                    json::SCHEMA_VERSION,
+                )?;
+                match location.filter(|_| !encrypted) {
+                    Some(location) => write!(
+                        out,
+                        ",\"location\":{{\"file\":{},\"line\":{},\"column\":{}}}",
+                        JsonStr(location.file()),
+                        location.line(),
+                        location.column(),
+                    )?,
+                    None => write!(out, ",\"location\":null")?,
+                }
+                if encrypted {
+                    writeln!(out, ",\"fingerprint\":null}}")?;
+                } else {
+                    writeln!(out, ",\"fingerprint\":\"{fingerprint:016x}\"}}")?;
+                }
+            }
+        }
+        out.flush()
+    }
+
+    fn write_report<W: Write>(
+        &self,
+        out: &mut W,
+        panic_info: &PanicHookInfo<'_>,
+        frames: &[Frame],
+        fingerprint: u64,
+        layout: Option<&MemoryLayout>,
+    ) -> io::Result<()> {
+        #[cfg(feature = "encrypt")]
+        if let Some(public_key) = &self.encrypt_to {
+            let mut report = Vec::new();
+            self.format_report(&mut report, panic_info, frames, fingerprint, layout)?;
+            match public_key.seal(&mut OsRng, &report) {
+                Ok(sealed) => armor::write_armored(out, &sealed)?,
+                // never fall back to printing the report in the clear.
+                Err(_) => writeln!(out, "enclave panic: failed to encrypt panic report")?,
+            }
+            return out.flush();
+        }
+
+        // format the report into a buffer on the stack and stream it out, so
+        // we don't need the heap (which might be what ran out).
+        let mut out = StackWriter::<_, REPORT_BUF_SIZE>::new(out);
+        self.format_report(&mut out, panic_info, frames, fingerprint, layout)?;
+
+        // let's try to flush so we get the full panic message out before the
+        // enclave aborts.
+        out.flush()
+    }
+
+    fn format_report<W: Write>(
+        &self,
+        out: &mut W,
+        panic_info: &PanicHookInfo<'_>,
+        frames: &[Frame],
+        fingerprint: u64,
+        layout: Option<&MemoryLayout>,
+    ) -> io::Result<()> {
+        let message_hash = match self.redaction {
+            Redaction::HashedMessage => {
+                catch::payload_str(panic_info.payload()).map(|message| fnv1a(message.as_bytes()))
+            }
+            Redaction::LocationOnly | Redaction::Full => None,
+        };
+
+        match self.format {
+            ReportFormat::Text => {
+                match self.redaction {
+                    // The default panic hook also doesn't print out the panic
+                    // message, so let's do that here.
+                    Redaction::Full => writeln!(out, "enclave panic: {panic_info}")?,
+                    Redaction::HashedMessage | Redaction::LocationOnly => {
+                        write!(out, "enclave panic: panicked")?;
+                        if let Some(location) = panic_info.location() {
+                            write!(out, " at {location}")?;
+                        }
+                        match message_hash {
+                            Some(hash) => writeln!(out, ":\n[redacted message {hash:016x}]")?,
+                            None => writeln!(out)?,
+                        }
+                    }
+                }
+                writeln!(out, "{FINGERPRINT_PREFIX}{fingerprint:016x}")?;
+                write!(out, "{}", DisplayFrames(frames))?;
+                if let Some(layout) = layout {
+                    write!(out, "{}", DisplayMemoryLayout(layout))?;
+                }
+                writeln!(out, "{DisplayContext}")?;
+            }
+            ReportFormat::Json => {
+                let message = match self.redaction {
+                    Redaction::Full => catch::payload_str(panic_info.payload()),
+                    Redaction::HashedMessage | Redaction::LocationOnly => None,
+                };
+                write!(
+                    out,
+                    "{{\"schema\":{},\"type\":\"panic\",\"message\":{}",
+                    json::SCHEMA_VERSION,
+                    JsonOptStr(message),
+                )?;
+                match message_hash {
+                    Some(hash) => write!(out, ",\"message_hash\":\"{hash:016x}\"")?,
+                    None => write!(out, ",\"message_hash\":null")?,
+                }
+                match panic_info.location() {
+                    Some(location) => write!(
+                        out,
+                        ",\"location\":{{\"file\":{},\"line\":{},\"column\":{}}}",
+                        JsonStr(location.file()),
+                        location.line(),
+                        location.column(),
+                    )?,
+                    None => write!(out, ",\"location\":null")?,
+                }
+                writeln!(
+                    out,
+                    ",\"thread\":{},\"build_id\":{},\"fingerprint\":\"{fingerprint:016x}\"{JsonContext}{},\"frames\":{}}}",
+                    JsonOptStr(thread::current().name()),
+                    JsonOptDisplay(BuildId::get()),
+                    JsonMemoryLayout(layout),
+                    JsonFrames(frames),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PanicHookBuilder {
+    /// Where to write the panic reports. Defaults to [`Output::Stdout`].
+    pub fn output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Print at most `frame_limit` frames per backtrace.
+    pub fn frame_limit(mut self, frame_limit: usize) -> Self {
+        self.frame_limit = frame_limit;
+        self
+    }
+
+    /// Whether to call the previously installed panic hook (by default, the
+    /// std hook) after printing the report.
+    ///
+    /// The std hook prints the full panic message, so this defaults to `true`
+    /// only if the [`Redaction`] is [`Full`](Redaction::Full) and the reports
+    /// aren't encrypted.
+    pub fn chain_prev_hook(mut self, chain_prev_hook: bool) -> Self {
+        self.chain_prev_hook = Some(chain_prev_hook);
+        self
+    }
+
+    /// How much of the panic message to print. Defaults to
+    /// [`Redaction::DEFAULT`], or [`Redaction::Full`] if the reports are
+    /// encrypted.
+    pub fn redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = Some(redaction);
+        self
+    }
+
+    /// Whether to print the panic payload (i.e., the panic message) or just
+    /// the panic location. Shorthand for [`Redaction::Full`] or
+    /// [`Redaction::LocationOnly`].
+    pub fn print_payload(self, print_payload: bool) -> Self {
+        self.redaction(if print_payload {
+            Redaction::Full
+        } else {
+            Redaction::LocationOnly
+        })
+    }
+
+    /// The panic report format. Defaults to [`ReportFormat::Text`].
+    pub fn format(mut self, format: ReportFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Add a `memory layout:` section (or `memory_layout` JSON field) to each
+    /// report, with the base and size of the image and heap, the current
+    /// thread's stack bounds, and how deep the panicking frame's stack pointer
+    /// went. Helps tell a stack overflow or heap exhaustion apart from an
+    /// ordinary panic. Defaults to `false`.
+    ///
+    /// ```text
+    /// memory layout:
+    ///   image: base 0x7f3a00000000, size 0x4000000
+    ///   heap: base 0x7f3a00200000, size 0x2000000
+    ///   stack: ?..0x7f3a03fc0000, sp 0x7f3a03fbf7a0 (0x860 bytes deep)
+    /// ```
+    ///
+    /// Inside SGX, the heap comes from the same loader-filled symbols as the
+    /// image base, but the ABI doesn't say how big each thread's stack is, so
+    /// its bottom is `?`. On Linux, there's no one heap to print, and the
+    /// addresses give away where ASLR put things.
+    ///
+    /// Keep in mind the hook itself needs about 10 KiB of stack (see
+    /// [`PanicHook`]), so a panic with less than that left won't get a report
+    /// at all.
+    pub fn memory_layout(mut self, memory_layout: bool) -> Self {
+        self.memory_layout = memory_layout;
+        self
+    }
+
+    /// Before handing each report to an [`Output::Custom`] writer, print a
+    /// one-line notice with the panic location and
+    /// [fingerprint](PanicHook#crash-fingerprints) to stderr. Defaults to
+    /// `false`, since the point of a custom output is often that stderr isn't
+    /// usable.
+    ///
+    /// ```text
+    /// enclave panic: panicked at bar.rs:10:5 (fingerprint: 5f0c2e8a9d41b7e3); writing the report
+    /// ```
+    ///
+    /// or with [`ReportFormat::Json`]:
+    ///
+    /// ```json
+    /// {"schema":1,"type":"panic_notice","encrypted":false,"location":{"file":"bar.rs","line":10,"column":5},"fingerprint":"5f0c2e8a9d41b7e3"}
+    /// ```
+    ///
+    /// If the writer panics, std aborts the process on the spot ("thread
+    /// panicked while processing panic") without ever calling the hook again,
+    /// so there's no way to fall back to another report after the fact. This
+    /// notice is all that's left of the panic then. With
+    /// [`encrypt_to`](Self::encrypt_to), it leaves out the location and
+    /// fingerprint.
+    pub fn stderr_notice(mut self, stderr_notice: bool) -> Self {
+        self.stderr_notice = stderr_notice;
+        self
+    }
+
+    /// Seal each report to the developer's X25519 `public_key`, so only the
+    /// holder of the matching secret key can read it. The whole report,
+    /// including the panic message and frame offsets, is encrypted and printed
+    /// as an armored base64 blob:
+    ///
+    /// ```text
+    /// -----BEGIN SGX PANIC REPORT-----
+    /// 8Jq0oOeJ0uC3KXqk3ss2Hh7WnOa4yx0m3ZzOZ0PuhW9bN5KrEo1Vxj4ZL0y0fQ2W
+    /// ...
+    /// -----END SGX PANIC REPORT-----
+    /// ```
+    ///
+    /// Generate a key pair with `sgx-panic-backtrace-keygen`, then decrypt and
+    /// symbolize the reports with `sgx-panic-backtrace-resolve --key`.
+    ///
+    /// Reports are sealed boxes (X25519 + XSalsa20-Poly1305, compatible with
+    /// libsodium's `crypto_box_seal`). Only available with the `encrypt`
+    /// feature.
+    #[cfg(feature = "encrypt")]
+    pub fn encrypt_to(mut self, public_key: [u8; 32]) -> Self {
+        self.encrypt_to = Some(PublicKey::from(public_key));
+        self
+    }
+
+    /// Install the panic hook, replacing the current one.
+    ///
+    /// With the default [`BacktraceStyle::Short`], the frames inside the panic
+    /// hook and std's panic machinery are skipped. To find std's frames without
+    /// any in-enclave symbolization, this triggers (and catches) a few panics
+    /// the first time it's called.
+    ///
+    /// If `SGX_PANIC_BACKTRACE` is set, this also sets the [`BacktraceStyle`]
+    /// from it. [`print_backtrace`](crate::print_backtrace) writes to this
+    /// hook's [`Output`] from now on, too.
+    pub fn install(self) {
+        if let Some(style) = BacktraceStyle::from_env() {
+            set_backtrace_style(style);
+        }
+        trace::calibrate();
+
+        let (redaction, chain_prev_hook) = self.redaction_and_chain_prev_hook();
+        let sink = match self.output {
+            Output::Stdout => {
+                // stdout allocates its line buffer on first use; get that out
+                // of the way now, while there's still heap to spare.
+                let _ = io::stdout();
+                Sink::Stdout
+            }
+            Output::Stderr => Sink::Stderr,
+            Output::Custom(writer) => Sink::Custom(Mutex::new(writer)),
+        };
+        let sink = Arc::new(sink);
+        *INSTALLED_SINK
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Arc::clone(&sink));
+
+        let prev_hook = panic::take_hook();
+        let hook = PanicHook {
+            sink,
+            frame_limit: self.frame_limit,
+            redaction,
+            format: self.format,
+            memory_layout: self.memory_layout,
+            stderr_notice: self.stderr_notice,
+            #[cfg(feature = "encrypt")]
+            encrypt_to: self.encrypt_to,
+            prev_hook: chain_prev_hook.then_some(prev_hook),
+        };
+        panic::set_hook(Box::new(move |panic_info| hook.call(panic_info)));
+    }
+
+    /// The [`Redaction`] and whether to chain the previous hook, with the
+    /// defaults filled in.
+    fn redaction_and_chain_prev_hook(&self) -> (Redaction, bool) {
+        #[cfg(feature = "encrypt")]
+        let encrypted = self.encrypt_to.is_some();
+        #[cfg(not(feature = "encrypt"))]
+        let encrypted = false;
+
+        // nobody but the developer can read an encrypted report, so there's no
+        // reason to hold anything back.
+        let redaction = self.redaction.unwrap_or(if encrypted {
+            Redaction::Full
+        } else {
+            Redaction::DEFAULT
+        });
+        let chain_prev_hook = self
+            .chain_prev_hook
+            .unwrap_or(redaction == Redaction::Full && !encrypted);
+        (redaction, chain_prev_hook)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_prev_hook_only_with_full_messages() {
+        let defaults = |redaction: Option<Redaction>| {
+            let builder = PanicHook::builder();
+            match redaction {
+                Some(redaction) => builder.redaction(redaction),
+                None => builder,
+            }
+            .redaction_and_chain_prev_hook()
+        };
+
+        assert_eq!(
+            defaults(None),
+            (Redaction::DEFAULT, Redaction::DEFAULT == Redaction::Full)
+        );
+        assert_eq!(
+            defaults(Some(Redaction::LocationOnly)),
+            (Redaction::LocationOnly, false)
+        );
+        assert_eq!(
+            defaults(Some(Redaction::HashedMessage)),
+            (Redaction::HashedMessage, false)
+        );
+        assert_eq!(defaults(Some(Redaction::Full)), (Redaction::Full, true));
+
+        // an explicit choice always wins
+        let builder = PanicHook::builder()
+            .redaction(Redaction::LocationOnly)
+            .chain_prev_hook(true);
+        assert_eq!(
+            builder.redaction_and_chain_prev_hook(),
+            (Redaction::LocationOnly, true)
+        );
+    }
+
+    #[cfg(feature = "encrypt")]
+    #[test]
+    fn encrypted_reports_are_full_and_never_chained() {
+        let builder = PanicHook::builder().encrypt_to([7; 32]);
+        assert_eq!(
+            builder.redaction_and_chain_prev_hook(),
+            (Redaction::Full, false)
+        );
+
+        for redaction in [
+            Redaction::LocationOnly,
+            Redaction::HashedMessage,
+            Redaction::Full,
+        ] {
+            let builder = PanicHook::builder()
+                .encrypt_to([7; 32])
+                .redaction(redaction);
+            assert_eq!(builder.redaction_and_chain_prev_hook(), (redaction, false));
+        }
+    }
+}
@@ -0,0 +1,1076 @@
+//! Host-side tooling for symbolizing the raw backtraces printed by the panic
+//! hook. This lives behind the `host` feature and shouldn't be built into the
+//! enclave itself.
+//!
+//! Keeping the parser next to the code that prints the frames means the two
+//! can't drift apart.
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    error::Error,
+    fmt, fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use addr2line::Loader;
+use crypto_box::aead::OsRng;
+use object::Object;
+
+use crate::{armor, build_id::BUILD_ID_PREFIX, BACKTRACE_HEADER, FINGERPRINT_PREFIX};
+
+/// Resolves relative frame offsets into function names and source locations
+/// using the DWARF debug info in the (unstripped) enclave ELF binary.
+pub struct Symbolizer {
+    loader: Loader,
+    build_id: Option<String>,
+}
+
+impl Symbolizer {
+    /// Load the debug info from the enclave ELF binary at `elf_path`.
+    pub fn new(elf_path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let elf_path = elf_path.as_ref();
+        let loader = Loader::new(elf_path)?;
+        let build_id = read_gnu_build_id(elf_path)?;
+        Ok(Self { loader, build_id })
+    }
+
+    /// The hex-encoded GNU build id (`.note.gnu.build-id`) of the ELF binary,
+    /// if it has one.
+    pub fn build_id(&self) -> Option<&str> {
+        self.build_id.as_deref()
+    }
+
+    /// Whether the backtrace from a build with the given (printed) build id
+    /// definitely didn't come from this binary. Build ids set with
+    /// [`set_build_id`](crate::set_build_id) can't be checked, so they never
+    /// mismatch.
+    fn mismatches(&self, build_id: &str) -> bool {
+        let is_gnu_build_id =
+            !build_id.is_empty() && build_id.bytes().all(|b| b.is_ascii_hexdigit());
+        match &self.build_id {
+            Some(ours) => is_gnu_build_id && !ours.eq_ignore_ascii_case(build_id),
+            None => false,
+        }
+    }
+
+    /// Write out the symbolized frame(s) for a single raw frame offset. A
+    /// single offset can expand to several frames if the call was inlined.
+    fn write_frame<W: Write>(&self, out: &mut W, frame_idx: usize, offset: u64) -> io::Result<()> {
+        // the frame offsets are return addresses, which point at the
+        // instruction _after_ the call. back up one byte so we land inside the
+        // call instruction and get the right line (and inline frames).
+        let probe = offset.saturating_sub(1);
+
+        let mut num_printed: usize = 0;
+        if let Ok(mut frames) = self.loader.find_frames(probe) {
+            while let Ok(Some(frame)) = frames.next() {
+                let name = frame
+                    .function
+                    .as_ref()
+                    .and_then(|name| name.demangle().ok())
+                    .map(|name| name.into_owned())
+                    .or_else(|| self.loader.find_symbol(probe).map(demangle))
+                    .unwrap_or_else(|| "<unknown>".to_owned());
+
+                write_frame_name(out, frame_idx, num_printed, offset, &name)?;
+                if let Some(loc) = frame.location {
+                    if let Some(file) = loc.file {
+                        write!(out, "             at {file}")?;
+                        if let Some(line) = loc.line {
+                            write!(out, ":{line}")?;
+                            if let Some(column) = loc.column {
+                                write!(out, ":{column}")?;
+                            }
+                        }
+                        writeln!(out)?;
+                    }
+                }
+                num_printed += 1;
+            }
+        }
+
+        // no DWARF info for this offset; fall back to the symbol table.
+        if num_printed == 0 {
+            let name = self
+                .loader
+                .find_symbol(probe)
+                .map(demangle)
+                .unwrap_or_else(|| "<unknown>".to_owned());
+            write_frame_name(out, frame_idx, 0, offset, &name)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Only the first (innermost) function for a frame gets the frame index, just
+/// like the std backtrace output. The callers it was inlined into are indented
+/// instead.
+fn write_frame_name<W: Write>(
+    out: &mut W,
+    frame_idx: usize,
+    symbol_idx: usize,
+    offset: u64,
+    name: &str,
+) -> io::Result<()> {
+    if symbol_idx == 0 {
+        writeln!(out, "{frame_idx:>4}: {offset:#x} - {name}")
+    } else {
+        writeln!(out, "      {offset:#x} - {name}")
+    }
+}
+
+/// Read the hex-encoded GNU build id of the ELF binary at `elf_path`.
+pub fn read_gnu_build_id(elf_path: impl AsRef<Path>) -> Result<Option<String>, Box<dyn Error>> {
+    let data = fs::read(elf_path)?;
+    let elf = object::File::parse(&*data)?;
+    Ok(elf.build_id()?.map(hex))
+}
+
+fn demangle(name: &str) -> String {
+    addr2line::demangle_auto(name.into(), None).into_owned()
+}
+
+/// A single raw backtrace frame line, e.g. `   3: 0x48b3ef`, or
+/// `   4: 0x29d90 (/lib/x86_64-linux-gnu/libc.so.6)` for a frame in a shared
+/// library. With [`BacktraceStyle::Full`](crate::BacktraceStyle::Full), the line
+/// ends with the frame's absolute addresses, e.g. `[ip 0x.., base 0x..,
+/// symbol 0x..]`, which are ignored.
+///
+/// A frame whose ip wasn't inside any loaded image is marked with `??`, e.g.
+/// `   7: ?? 0x10`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameLine<'a> {
+    pub frame_idx: usize,
+    /// The frame's offset, relative to the base of the image it's in. For a
+    /// frame outside any image, the absolute ip.
+    pub offset: u64,
+    /// The path of the shared library containing the frame, or `None` for the
+    /// main enclave (or executable) image.
+    pub module: Option<&'a str>,
+    /// Whether the frame's ip was inside a loaded image. If not, there's
+    /// nothing to symbolize.
+    pub in_image: bool,
+}
+
+/// Parse a single raw backtrace frame line. See [`FrameLine`].
+pub fn parse_frame_line(line: &str) -> Option<FrameLine<'_>> {
+    let (frame_idx, rest) = line.trim().split_once(": ")?;
+    let rest = match rest.strip_suffix(']') {
+        Some(rest) => rest.rsplit_once(" [")?.0,
+        None => rest,
+    };
+    let (rest, in_image) = match rest.strip_prefix("?? ") {
+        Some(rest) => (rest, false),
+        None => (rest, true),
+    };
+    let (offset, module) = match rest.split_once(' ') {
+        Some((offset, module)) => {
+            let module = module.strip_prefix('(')?.strip_suffix(')')?;
+            (offset, Some(module))
+        }
+        None => (rest, None),
+    };
+    let frame_idx = frame_idx.parse().ok()?;
+    let offset = offset.strip_prefix("0x")?;
+    let offset = u64::from_str_radix(offset, 16).ok()?;
+    Some(FrameLine {
+        frame_idx,
+        offset,
+        module,
+        in_image,
+    })
+}
+
+/// The developer's X25519 secret key, for decrypting the reports from a panic
+/// hook configured with
+/// [`encrypt_to`](crate::PanicHookBuilder::encrypt_to).
+pub struct SecretKey(crypto_box::SecretKey);
+
+impl SecretKey {
+    /// Generate a new random secret key.
+    pub fn generate() -> Self {
+        Self(crypto_box::SecretKey::generate(&mut OsRng))
+    }
+
+    /// Parse a secret key from its hex encoding, as written by
+    /// [`to_hex`](Self::to_hex). Surrounding whitespace is ignored.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.trim();
+        if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let mut bytes = [0u8; 32];
+        for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).ok()?;
+            *byte = u8::from_str_radix(digits, 16).ok()?;
+        }
+        Some(Self(crypto_box::SecretKey::from(bytes)))
+    }
+
+    /// The hex encoding of the secret key.
+    pub fn to_hex(&self) -> String {
+        hex(&self.0.to_bytes())
+    }
+
+    /// The matching public key, to pass to
+    /// [`encrypt_to`](crate::PanicHookBuilder::encrypt_to).
+    pub fn public_key(&self) -> [u8; 32] {
+        self.0.public_key().to_bytes()
+    }
+
+    /// Decrypt a single armored report, returning the plaintext report.
+    fn decrypt(&self, armored: &str) -> Option<Vec<u8>> {
+        let body = armored
+            .trim()
+            .strip_prefix(armor::BEGIN)?
+            .strip_suffix(armor::END)?;
+        let sealed = armor::decode_base64(body)?;
+        self.0.unseal(&sealed).ok()
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+/// Lowercase hex encoding of `bytes`.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// A directory of enclave ELF binaries, keyed by build id:
+/// `<store>/<build-id>/enclave.elf`. Debuginfod's cache layout,
+/// `<store>/<build-id>/debuginfo` (or `executable`), works too.
+///
+/// With a store, the resolver picks the right ELF for each backtrace from its
+/// `build id:` line, so symbolizing a report from an old release doesn't
+/// involve digging up that release's binary.
+#[derive(Clone, Debug)]
+pub struct SymbolStore {
+    dir: PathBuf,
+}
+
+impl SymbolStore {
+    /// The file names we look for in each `<store>/<build-id>/` directory.
+    const FILE_NAMES: [&'static str; 3] = ["enclave.elf", "debuginfo", "executable"];
+
+    /// Use the store in `dir`. It doesn't need to exist yet.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The path of the ELF binary with the given `build_id`, if it's in the
+    /// store.
+    pub fn find(&self, build_id: &str) -> Option<PathBuf> {
+        let build_dir = self.build_dir(build_id)?;
+        Self::FILE_NAMES
+            .iter()
+            .map(|file_name| build_dir.join(file_name))
+            .find(|path| path.is_file())
+    }
+
+    /// Copy the ELF binary at `elf_path` into the store and return its new
+    /// path. The binary is stored under `build_id`, or its GNU build id if
+    /// that's `None`.
+    ///
+    /// Enclaves that [set their own build id](crate::set_build_id) need to
+    /// pass it in here, since it isn't in the binary.
+    pub fn add(
+        &self,
+        elf_path: impl AsRef<Path>,
+        build_id: Option<&str>,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let elf_path = elf_path.as_ref();
+        let build_id = match build_id {
+            Some(build_id) => build_id.to_owned(),
+            None => read_gnu_build_id(elf_path)?
+                .ok_or("ELF doesn't have a GNU build id; pass one in explicitly")?,
+        };
+        let build_dir = self
+            .build_dir(&build_id)
+            .ok_or_else(|| format!("invalid build id: '{build_id}'"))?;
+        fs::create_dir_all(&build_dir)?;
+
+        // copy then rename, so a concurrent `find` never sees half an ELF.
+        let path = build_dir.join(Self::FILE_NAMES[0]);
+        let tmp_path = path.with_extension("elf.tmp");
+        fs::copy(elf_path, &tmp_path)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(path)
+    }
+
+    /// The directory for `build_id`, or `None` if it's not a valid path
+    /// component.
+    fn build_dir(&self, build_id: &str) -> Option<PathBuf> {
+        let is_valid = !build_id.is_empty()
+            && build_id != "."
+            && build_id != ".."
+            && !build_id.contains(['/', '\\']);
+        is_valid.then(|| self.dir.join(build_id))
+    }
+}
+
+/// Symbolizes the enclave output one line at a time.
+struct LineResolver<'a> {
+    /// The enclave ELF to symbolize against if the store doesn't have a
+    /// better match.
+    symbolizer: Option<&'a Symbolizer>,
+    store: Option<&'a SymbolStore>,
+    /// The ELFs we've loaded from the store, by build id. `None` if it's not
+    /// in the store or failed to load.
+    builds: HashMap<String, Option<Symbolizer>>,
+    modules: HashMap<String, Option<Symbolizer>>,
+    /// The build id of the next (or current) backtrace.
+    build_id: Option<String>,
+    in_backtrace: bool,
+}
+
+impl LineResolver<'_> {
+    /// The symbolizer for the main image of the next (or current) backtrace,
+    /// or `None` if we don't have the right ELF.
+    fn main_symbolizer(&self) -> Option<&Symbolizer> {
+        let Some(build_id) = &self.build_id else {
+            return self.symbolizer;
+        };
+        if let Some(Some(symbolizer)) = self.builds.get(build_id) {
+            return Some(symbolizer);
+        }
+        self.symbolizer
+            .filter(|symbolizer| !symbolizer.mismatches(build_id))
+    }
+
+    fn set_build_id<W: Write>(&mut self, output: &mut W, build_id: &str) -> io::Result<()> {
+        if let Some(store) = self.store {
+            self.builds.entry(build_id.to_owned()).or_insert_with(|| {
+                store
+                    .find(build_id)
+                    .and_then(|path| Symbolizer::new(path).ok())
+            });
+        }
+        self.build_id = Some(build_id.to_owned());
+
+        if self.main_symbolizer().is_some() {
+            return Ok(());
+        }
+        let not_in_store = self.store.map(|_| "the build id isn't in the symbol store");
+        let mismatch = self.symbolizer.map(|symbolizer| {
+            let ours = symbolizer.build_id().unwrap_or_default();
+            format!("the enclave ELF's build id is {ours}")
+        });
+        match (not_in_store, mismatch) {
+            (Some(not_in_store), Some(mismatch)) => {
+                writeln!(
+                    output,
+                    "note: not symbolizing; {not_in_store}, and {mismatch}"
+                )
+            }
+            (Some(reason), None) => writeln!(output, "note: not symbolizing; {reason}"),
+            (None, Some(reason)) => writeln!(output, "note: not symbolizing; {reason}"),
+            (None, None) => Ok(()),
+        }
+    }
+
+    fn write_line<W: Write>(&mut self, output: &mut W, line: &[u8]) -> io::Result<()> {
+        // enclave output isn't guaranteed to be valid UTF-8; only lines we
+        // actually rewrite need to be.
+        let text = std::str::from_utf8(line).unwrap_or("");
+
+        if self.in_backtrace {
+            if let Some(frame) = parse_frame_line(text) {
+                // symbolizing a garbage ip would only point somewhere unrelated
+                if !frame.in_image {
+                    return output.write_all(line);
+                }
+                let symbolizer = match frame.module {
+                    None => self.main_symbolizer(),
+                    Some(module) => self
+                        .modules
+                        .entry(module.to_owned())
+                        .or_insert_with(|| Symbolizer::new(module).ok())
+                        .as_ref(),
+                };
+                return match symbolizer {
+                    Some(symbolizer) => {
+                        symbolizer.write_frame(output, frame.frame_idx, frame.offset)
+                    }
+                    // can't find the binary; leave the frame as-is
+                    None => output.write_all(line),
+                };
+            }
+            // first non-frame line ends the block
+            self.in_backtrace = false;
+            self.build_id = None;
+        }
+
+        if let Some(build_id) = text.trim().strip_prefix(BUILD_ID_PREFIX) {
+            output.write_all(line)?;
+            return self.set_build_id(output, build_id);
+        }
+
+        if text.trim_end() == BACKTRACE_HEADER {
+            self.in_backtrace = true;
+        }
+        output.write_all(line)
+    }
+
+    /// Forget about any backtrace we're in the middle of.
+    fn reset(&mut self) {
+        self.in_backtrace = false;
+        self.build_id = None;
+    }
+}
+
+/// Symbolizes (and decrypts) the enclave output. For the common cases, see
+/// [`resolve`] and [`resolve_encrypted`].
+///
+/// ```rust,no_run
+/// use sgx_panic_backtrace::host::{Resolver, SymbolStore};
+/// use std::io;
+///
+/// let store = SymbolStore::new("symbols");
+/// Resolver::new()
+///     .store(&store)
+///     .resolve(io::stdin().lock(), io::stdout().lock())
+///     .unwrap();
+/// ```
+#[derive(Default)]
+#[must_use]
+pub struct Resolver<'a> {
+    symbolizer: Option<&'a Symbolizer>,
+    store: Option<&'a SymbolStore>,
+    secret_key: Option<&'a SecretKey>,
+}
+
+impl<'a> Resolver<'a> {
+    /// A resolver that doesn't know about any ELF binaries yet, so it passes
+    /// everything through untouched.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Symbolize the backtraces against this enclave ELF, unless the
+    /// [`store`](Self::store) has the ELF for the backtrace's build id.
+    pub fn symbolizer(mut self, symbolizer: &'a Symbolizer) -> Self {
+        self.symbolizer = Some(symbolizer);
+        self
+    }
+
+    /// Look up the ELF for each backtrace in `store`, by its build id.
+    pub fn store(mut self, store: &'a SymbolStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Decrypt the armored reports from a panic hook configured with
+    /// [`encrypt_to`](crate::PanicHookBuilder::encrypt_to), then symbolize the
+    /// decrypted reports in place. Reports that can't be decrypted with
+    /// `secret_key` are passed through untouched.
+    pub fn secret_key(mut self, secret_key: &'a SecretKey) -> Self {
+        self.secret_key = Some(secret_key);
+        self
+    }
+
+    /// Copy the enclave output from `input` to `output`, symbolizing the frames
+    /// in every `stack backtrace:` block along the way. All other lines are
+    /// passed through untouched.
+    ///
+    /// Frames in shared libraries (only on Linux; enclaves don't have any) are
+    /// symbolized against the library at the same path on this machine, if it
+    /// exists.
+    pub fn resolve<R: BufRead, W: Write>(self, mut input: R, mut output: W) -> io::Result<()> {
+        let mut lines = LineResolver {
+            symbolizer: self.symbolizer,
+            store: self.store,
+            builds: HashMap::new(),
+            modules: HashMap::new(),
+            build_id: None,
+            in_backtrace: false,
+        };
+        let mut line = Vec::new();
+        // the armored report we're in the middle of, if any.
+        let mut armored: Option<Vec<u8>> = None;
+
+        loop {
+            line.clear();
+            if input.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+
+            if let Some(secret_key) = self.secret_key {
+                let text = std::str::from_utf8(&line).unwrap_or("").trim();
+                if let Some(report) = &mut armored {
+                    report.extend_from_slice(&line);
+                    if text == armor::END {
+                        let report = armored.take().unwrap_or_default();
+                        let plaintext = std::str::from_utf8(&report)
+                            .ok()
+                            .and_then(|report| secret_key.decrypt(report));
+                        match plaintext {
+                            Some(plaintext) => {
+                                lines.reset();
+                                for line in plaintext.split_inclusive(|b| *b == b'\n') {
+                                    lines.write_line(&mut output, line)?;
+                                }
+                                lines.reset();
+                            }
+                            // wrong key or mangled report
+                            None => output.write_all(&report)?,
+                        }
+                    }
+                    continue;
+                }
+                if text == armor::BEGIN {
+                    armored = Some(line.clone());
+                    continue;
+                }
+            }
+
+            lines.write_line(&mut output, &line)?;
+        }
+
+        // the output got cut off in the middle of a report
+        if let Some(report) = armored {
+            output.write_all(&report)?;
+        }
+
+        output.flush()
+    }
+}
+
+/// Copy the enclave output from `input` to `output`, symbolizing the frames in
+/// every `stack backtrace:` block along the way. All other lines are passed
+/// through untouched. See [`Resolver::resolve`].
+pub fn resolve<R: BufRead, W: Write>(
+    symbolizer: &Symbolizer,
+    input: R,
+    output: W,
+) -> io::Result<()> {
+    Resolver::new()
+        .symbolizer(symbolizer)
+        .resolve(input, output)
+}
+
+/// Like [`resolve`], but also decrypts the armored reports from a panic hook
+/// configured with [`encrypt_to`](crate::PanicHookBuilder::encrypt_to) and
+/// symbolizes the decrypted reports in place. Reports that can't be decrypted
+/// with `secret_key` are passed through untouched.
+pub fn resolve_encrypted<R: BufRead, W: Write>(
+    symbolizer: &Symbolizer,
+    secret_key: &SecretKey,
+    input: R,
+    output: W,
+) -> io::Result<()> {
+    Resolver::new()
+        .symbolizer(symbolizer)
+        .secret_key(secret_key)
+        .resolve(input, output)
+}
+
+/// A group of panic reports with the same crash fingerprint. See
+/// [`bucket_reports`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CrashBucket {
+    /// The hex-encoded crash fingerprint shared by every report in the bucket.
+    pub fingerprint: String,
+    /// The number of reports in the bucket.
+    pub count: usize,
+    /// The first report in the bucket, as it appeared in the logs.
+    pub example: String,
+}
+
+/// A text panic report we're in the middle of reading.
+struct PendingReport {
+    text: String,
+    fingerprint: Option<String>,
+}
+
+/// Groups panic reports by their crash fingerprint, across any number of
+/// logs. For a single log, see [`bucket_reports`].
+#[derive(Debug, Default)]
+pub struct CrashBuckets {
+    buckets: Vec<CrashBucket>,
+    by_fingerprint: HashMap<String, usize>,
+}
+
+impl CrashBuckets {
+    /// No buckets yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the panic reports in the enclave output from `input`. Takes both
+    /// text and JSON reports, raw or already symbolized (e.g. decrypted with
+    /// [`resolve_encrypted`]). Everything else is ignored, as are reports
+    /// without a fingerprint, e.g. from an older version of this crate.
+    pub fn read_reports<R: BufRead>(&mut self, mut input: R) -> io::Result<()> {
+        let mut report: Option<PendingReport> = None;
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            if input.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let text = String::from_utf8_lossy(&line);
+            let trimmed = text.trim();
+
+            if trimmed.starts_with('{') && trimmed.contains("\"type\":\"panic\"") {
+                self.finish(report.take());
+                if let Some(fingerprint) = json_fingerprint(trimmed) {
+                    self.add(fingerprint, &text);
+                }
+                continue;
+            }
+
+            if text.starts_with("enclave panic: ") {
+                self.finish(report.take());
+                report = Some(PendingReport {
+                    text: String::new(),
+                    fingerprint: None,
+                });
+            }
+            let Some(pending) = &mut report else {
+                continue;
+            };
+
+            // the report always ends with a blank line. the panic message
+            // (which might have blank lines of its own) comes before the
+            // fingerprint, and the backtrace (if any) after it.
+            if pending.fingerprint.is_some() && trimmed.is_empty() {
+                self.finish(report.take());
+                continue;
+            }
+            pending.text.push_str(&text);
+            if let Some(fingerprint) = trimmed.strip_prefix(FINGERPRINT_PREFIX) {
+                pending.fingerprint = Some(fingerprint.to_owned());
+            }
+        }
+        self.finish(report);
+        Ok(())
+    }
+
+    /// The buckets, sorted by count, most common first.
+    pub fn into_buckets(self) -> Vec<CrashBucket> {
+        let mut buckets = self.buckets;
+        // stable, so ties stay in the order we first saw them.
+        buckets.sort_by_key(|bucket| Reverse(bucket.count));
+        buckets
+    }
+
+    fn add(&mut self, fingerprint: &str, report: &str) {
+        match self.by_fingerprint.get(fingerprint) {
+            Some(idx) => self.buckets[*idx].count += 1,
+            None => {
+                self.by_fingerprint
+                    .insert(fingerprint.to_owned(), self.buckets.len());
+                self.buckets.push(CrashBucket {
+                    fingerprint: fingerprint.to_owned(),
+                    count: 1,
+                    example: report.to_owned(),
+                });
+            }
+        }
+    }
+
+    fn finish(&mut self, report: Option<PendingReport>) {
+        if let Some(PendingReport {
+            text,
+            fingerprint: Some(fingerprint),
+            ..
+        }) = report
+        {
+            self.add(&fingerprint, &text);
+        }
+    }
+}
+
+/// Group the panic reports in the enclave output from `input` by their crash
+/// fingerprint. See [`CrashBuckets::read_reports`].
+pub fn bucket_reports<R: BufRead>(input: R) -> io::Result<Vec<CrashBucket>> {
+    let mut buckets = CrashBuckets::new();
+    buckets.read_reports(input)?;
+    Ok(buckets.into_buckets())
+}
+
+/// Pull the `"fingerprint"` out of a JSON panic report. Fingerprints are just
+/// hex digits, so there's nothing to unescape.
+fn json_fingerprint(report: &str) -> Option<&str> {
+    let (_, rest) = report.split_once("\"fingerprint\":\"")?;
+    let (fingerprint, _) = rest.split_once('"')?;
+    Some(fingerprint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(frame_idx: usize, offset: u64, module: Option<&str>, in_image: bool) -> FrameLine<'_> {
+        FrameLine {
+            frame_idx,
+            offset,
+            module,
+            in_image,
+        }
+    }
+
+    #[test]
+    fn parse_frame_lines() {
+        assert_eq!(
+            parse_frame_line("   3: 0x48b3ef\n"),
+            Some(frame(3, 0x48b3ef, None, true)),
+        );
+        assert_eq!(
+            parse_frame_line("   8: 0x2724a (/lib/x86_64-linux-gnu/libc.so.6)"),
+            Some(frame(
+                8,
+                0x2724a,
+                Some("/lib/x86_64-linux-gnu/libc.so.6"),
+                true
+            )),
+        );
+        assert_eq!(
+            parse_frame_line("  12: 0x1f00 (/opt/my app/lib (old).so)"),
+            Some(frame(12, 0x1f00, Some("/opt/my app/lib (old).so"), true)),
+        );
+        assert_eq!(
+            parse_frame_line("   7: ?? 0xdeadbeef"),
+            Some(frame(7, 0xdeadbeef, None, false)),
+        );
+        assert_eq!(
+            parse_frame_line(
+                "   0: 0x1b09d9 [ip 0x7f3a001b09d9, base 0x7f3a00000000, symbol 0x7f3a001b0990]"
+            ),
+            Some(frame(0, 0x1b09d9, None, true)),
+        );
+        assert_eq!(
+            parse_frame_line(
+                "   1: 0x2724a (/lib/libc.so.6) [ip 0x7f00002724a, base 0x7f000000000, symbol 0x0]"
+            ),
+            Some(frame(1, 0x2724a, Some("/lib/libc.so.6"), true)),
+        );
+        assert_eq!(
+            parse_frame_line("   2: ?? 0x10 [ip 0x10, base 0x0, symbol 0x0]"),
+            Some(frame(2, 0x10, None, false)),
+        );
+
+        for line in [
+            "",
+            BACKTRACE_HEADER,
+            "memory layout:",
+            "  image: base 0x7f3a00000000, size 0x4000000",
+            "context:",
+            "  - handling request 7: 0x10",
+            "   3: 48b3ef",
+            "   3: 0xzz",
+            "   x: 0x10",
+            "   3: 0x10 libc.so.6",
+        ] {
+            assert_eq!(parse_frame_line(line), None, "{line:?}");
+        }
+    }
+
+    /// A frame offset in this test binary that symbolizes to `marker`.
+    #[cfg(target_os = "linux")]
+    fn marker_offset() -> u64 {
+        #[inline(never)]
+        fn marker() {}
+
+        let ip = marker as *const () as usize;
+        let base = crate::image::module_base(ip).unwrap();
+        // frame offsets are return addresses, so the resolver backs up a byte.
+        (ip - base + 1) as u64
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn backtrace_blocks_end_at_the_first_non_frame_line() {
+        let symbolizer = Symbolizer::new(std::env::current_exe().unwrap()).unwrap();
+        let offset = marker_offset();
+        let frame_line = format!("   0: {offset:#x}\n");
+
+        for end in ["memory layout:\n", "context:\n", "breadcrumbs:\n", "\n"] {
+            let input = format!(
+                "enclave panic: panicked at bar.rs:10:5\n{BACKTRACE_HEADER}\n{frame_line}   1: ?? 0x10\n{end}{frame_line}"
+            );
+            let mut output = Vec::new();
+            resolve(&symbolizer, input.as_bytes(), &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+
+            let (block, after) = output.split_once(&format!("\n{end}")).unwrap();
+            let symbolized = format!(
+                "   0: {offset:#x} - sgx_panic_backtrace::host::tests::marker_offset::marker\n"
+            );
+            assert!(block.contains(&symbolized), "{output}");
+            // garbage ips are left alone
+            assert!(block.ends_with("\n   1: ?? 0x10"), "{output}");
+            assert_eq!(after, frame_line, "{end:?}");
+        }
+    }
+
+    #[test]
+    fn secret_key_hex() {
+        let key = SecretKey::generate();
+        let parsed = SecretKey::from_hex(&format!("  {}\n", key.to_hex())).unwrap();
+        assert_eq!(parsed.public_key(), key.public_key());
+        assert_eq!(parsed.to_hex(), key.to_hex());
+
+        let hex = key.to_hex();
+        for bad in [
+            String::new(),
+            hex[..62].to_owned(),
+            format!("{hex}00"),
+            format!("zz{}", &hex[2..]),
+            // `u8::from_str_radix` takes a sign
+            format!("+f{}", &hex[2..]),
+        ] {
+            assert!(SecretKey::from_hex(&bad).is_none(), "{bad:?}");
+        }
+    }
+
+    fn seal_report(public_key: [u8; 32], report: &str) -> String {
+        let sealed = crypto_box::PublicKey::from(public_key)
+            .seal(&mut OsRng, report.as_bytes())
+            .unwrap();
+        let mut armored = Vec::new();
+        armor::write_armored(&mut armored, &sealed).unwrap();
+        String::from_utf8(armored).unwrap()
+    }
+
+    #[test]
+    fn resolve_encrypted_reports() {
+        let key = SecretKey::generate();
+        let report = format!(
+            "enclave panic: panicked at bar.rs:10:5\nfingerprint: 5f0c2e8a9d41b7e3\n{BACKTRACE_HEADER}\n   0: 0x10\n\n"
+        );
+        let sealed = seal_report(key.public_key(), &report);
+        let input = format!("starting enclave\n{sealed}exiting\n");
+
+        let mut output = Vec::new();
+        Resolver::new()
+            .secret_key(&key)
+            .resolve(input.as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("starting enclave\n{report}exiting\n"),
+        );
+
+        // the wrong key, a mangled report, or one that got cut off pass through
+        let wrong_key = SecretKey::generate();
+        let mangled = sealed.replacen(armor::BEGIN, &format!("{}\nAAAA", armor::BEGIN), 1);
+        let cut_off = sealed
+            .lines()
+            .take(2)
+            .map(|line| format!("{line}\n"))
+            .collect::<String>();
+        for (key, input) in [(&wrong_key, &sealed), (&key, &mangled), (&key, &cut_off)] {
+            let mut output = Vec::new();
+            Resolver::new()
+                .secret_key(key)
+                .resolve(input.as_bytes(), &mut output)
+                .unwrap();
+            assert_eq!(&String::from_utf8(output).unwrap(), input);
+        }
+    }
+
+    fn text_report(location: &str, message: &str, fingerprint: &str, frames: &str) -> String {
+        format!(
+            "enclave panic: panicked at {location}:\n{message}\n{FINGERPRINT_PREFIX}{fingerprint}\n{frames}\n"
+        )
+    }
+
+    #[test]
+    fn bucket_text_reports() {
+        let frames = format!("{BACKTRACE_HEADER}\n   0: 0x1b09d9\n   1: 0x1396f6\n");
+        let foo = text_report(
+            "bar.rs:10:5",
+            "foo\n\nwith a blank line",
+            "00000000000000aa",
+            &frames,
+        );
+        let foo_again = text_report("bar.rs:10:5", "foo again", "00000000000000aa", &frames);
+        let context = format!("{frames}context:\n  - handling request 7\n");
+        let baz = text_report("baz.rs:3:9", "baz", "00000000000000bb", &context);
+        // `SGX_PANIC_BACKTRACE=off`: no backtrace at all
+        let off = text_report("qux.rs:1:1", "qux", "00000000000000cc", "");
+        let no_fingerprint = "enclave panic: panicked at old.rs:1:1:\nold\n\n".to_owned();
+        let input = format!(
+            "starting\n{foo}app log\n{baz}{off}unrelated app log\n{no_fingerprint}{foo_again}more app logs\n"
+        );
+
+        let buckets = bucket_reports(input.as_bytes()).unwrap();
+        let buckets = buckets
+            .iter()
+            .map(|bucket| {
+                (
+                    bucket.fingerprint.as_str(),
+                    bucket.count,
+                    bucket.example.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let trim = |report: &str| report.strip_suffix('\n').unwrap().to_owned();
+        assert_eq!(
+            buckets,
+            [
+                ("00000000000000aa", 2, &*trim(&foo)),
+                ("00000000000000bb", 1, &*trim(&baz)),
+                ("00000000000000cc", 1, &*trim(&off)),
+            ],
+        );
+    }
+
+    #[test]
+    fn bucket_json_reports() {
+        let report = |fingerprint: &str| {
+            format!(
+                "{{\"schema\":1,\"type\":\"panic\",\"message\":\"foo\",\"fingerprint\":\"{fingerprint}\",\"frames\":[{{\"offset\":\"0x10\"}}]}}\n"
+            )
+        };
+        let (aa, bb) = (report("00000000000000aa"), report("00000000000000bb"));
+        let input = format!(
+            "{bb}app log\n{aa}{{\"type\":\"request\",\"fingerprint\":\"00000000000000ff\"}}\n{aa}"
+        );
+
+        let mut buckets = CrashBuckets::new();
+        buckets.read_reports(input.as_bytes()).unwrap();
+        // across logs, and mixed with text reports
+        let text = text_report("bar.rs:10:5", "foo", "00000000000000bb", "");
+        buckets.read_reports(text.as_bytes()).unwrap();
+
+        assert_eq!(
+            buckets.into_buckets(),
+            [
+                // ties stay in the order we first saw them
+                CrashBucket {
+                    fingerprint: "00000000000000bb".to_owned(),
+                    count: 2,
+                    example: bb,
+                },
+                CrashBucket {
+                    fingerprint: "00000000000000aa".to_owned(),
+                    count: 2,
+                    example: aa,
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn lines_outside_backtraces_pass_through() {
+        let input = b"   0: 0x10\nenclave panic: panicked at bar.rs:10:5\n\xff\xfe not utf-8\n";
+        let mut output = Vec::new();
+        Resolver::new().resolve(&input[..], &mut output).unwrap();
+        assert_eq!(output, input);
+    }
+
+    /// A fresh directory under the system temp dir, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("sgx-panic-backtrace-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn dir_entries(dir: &Path) -> Vec<String> {
+        let mut entries = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn symbol_store_add_and_find() {
+        let tmp = TempDir::new("store-add");
+        let store = SymbolStore::new(tmp.0.join("store"));
+        let elf_path = std::env::current_exe().unwrap();
+
+        assert_eq!(store.find("my-enclave-0.3.1"), None);
+        let path = store.add(&elf_path, Some("my-enclave-0.3.1")).unwrap();
+        assert_eq!(path, tmp.0.join("store/my-enclave-0.3.1/enclave.elf"));
+        assert_eq!(fs::read(&path).unwrap(), fs::read(&elf_path).unwrap());
+        assert_eq!(store.find("my-enclave-0.3.1"), Some(path.clone()));
+        assert_eq!(store.find("my-enclave-0.3.2"), None);
+
+        // the copy goes to a temp file first, which gets renamed into place,
+        // even over a stale one from an interrupted add, or an older copy.
+        let build_dir = tmp.0.join("store/my-enclave-0.3.1");
+        assert_eq!(dir_entries(&build_dir), ["enclave.elf"]);
+        fs::write(build_dir.join("enclave.elf.tmp"), b"half an elf").unwrap();
+        fs::write(&path, b"an old elf").unwrap();
+        assert_eq!(
+            store.add(&elf_path, Some("my-enclave-0.3.1")).unwrap(),
+            path
+        );
+        assert_eq!(fs::read(&path).unwrap(), fs::read(&elf_path).unwrap());
+        assert_eq!(dir_entries(&build_dir), ["enclave.elf"]);
+
+        // a failed copy leaves nothing to find
+        assert!(store
+            .add(tmp.0.join("missing.elf"), Some("my-enclave-0.3.3"))
+            .is_err());
+        assert_eq!(store.find("my-enclave-0.3.3"), None);
+    }
+
+    #[test]
+    fn symbol_store_finds_debuginfod_layouts() {
+        let tmp = TempDir::new("store-debuginfod");
+        let store = SymbolStore::new(&tmp.0);
+        let build_dir = tmp.0.join("0123abcd");
+        fs::create_dir_all(&build_dir).unwrap();
+
+        // a directory with the right name doesn't count
+        fs::create_dir_all(build_dir.join("debuginfo")).unwrap();
+        assert_eq!(store.find("0123abcd"), None);
+        fs::remove_dir(build_dir.join("debuginfo")).unwrap();
+
+        fs::write(build_dir.join("executable"), b"").unwrap();
+        assert_eq!(store.find("0123abcd"), Some(build_dir.join("executable")));
+
+        // the debug info beats the stripped executable
+        fs::write(build_dir.join("debuginfo"), b"").unwrap();
+        assert_eq!(store.find("0123abcd"), Some(build_dir.join("debuginfo")));
+
+        // and one we added ourselves beats both
+        fs::write(build_dir.join("enclave.elf"), b"").unwrap();
+        assert_eq!(store.find("0123abcd"), Some(build_dir.join("enclave.elf")));
+    }
+
+    #[test]
+    fn symbol_store_rejects_invalid_build_ids() {
+        let tmp = TempDir::new("store-invalid");
+        let store = SymbolStore::new(tmp.0.join("store"));
+        let elf_path = std::env::current_exe().unwrap();
+
+        // e.g. a build id of `..` would otherwise find `<store>/../enclave.elf`
+        fs::write(tmp.0.join("enclave.elf"), b"").unwrap();
+        for build_id in ["", ".", "..", "../store", "a/b", "/etc", "a\\b"] {
+            assert_eq!(store.find(build_id), None, "{build_id:?}");
+            let err = store.add(&elf_path, Some(build_id)).unwrap_err();
+            assert!(
+                err.to_string().starts_with("invalid build id"),
+                "{build_id:?}: {err}"
+            );
+        }
+        // nothing got written anywhere
+        assert_eq!(dir_entries(&tmp.0), ["enclave.elf"]);
+    }
+}
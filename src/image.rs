@@ -0,0 +1,506 @@
+//! Finding the load base address of the binary image containing a given
+//! instruction pointer, so we can print offsets a symbolizer can actually use.
+//!
+//! + In an SGX enclave, there's just the one enclave image.
+//! + On Linux (including library OSes like Gramine and Occlum), the main
+//!   executable and each shared library get loaded at their own (randomized)
+//!   base address, so we look up the module containing the ip with
+//!   `dl_iterate_phdr`.
+//! + Everywhere else, we don't know the base, so the offsets are just the
+//!   absolute addresses.
+//!
+//! On Linux, we also read the main executable's GNU build id out of its loaded
+//! `PT_NOTE` segment.
+//!
+//! For the [frame pointer walker](crate::frame_pointer), we also need to know
+//! which addresses are safe to read as stack, and which could be return
+//! addresses. And for the [memory layout](crate::layout) section of the panic
+//! report, where the image, heap, and current thread's stack are.
+
+use std::{fmt, ops::Range};
+
+#[cfg(all(target_vendor = "fortanix", target_env = "sgx"))]
+mod imp {
+    use std::{fmt, ops::Range};
+
+    /// Return the base address of the currently loaded SGX enclave binary. Vendoring
+    /// this lets us avoid requiring the unstable `sgx_platform` feature.
+    ///
+    /// This is copied from: [std::os::fortanix_sgx::mem::image_base](https://github.com/rust-lang/rust/blob/master/library/std/src/sys/sgx/abi/mem.rs#L37)
+    // NOTE: Do not remove inline: will result in relocation failure.
+    #[inline(always)]
+    pub(crate) fn image_base() -> u64 {
+        use std::arch::asm;
+
+        let base: u64;
+        unsafe {
+            asm!(
+                // `IMAGE_BASE` is defined here:
+                // [std/src/sys/sgx/abi/entry.S](https://github.com/rust-lang/rust/blob/master/library/std/src/sys/sgx/abi/entry.S#L5)
+                "lea IMAGE_BASE(%rip), {}",
+                lateout(reg) base,
+                options(att_syntax, nostack, preserves_flags, nomem, pure),
+            )
+        };
+        base
+    }
+
+    /// Return the size of the loaded SGX enclave, image and all: heap, stacks,
+    /// TCSs, etc... Like `image_base`, this is vendored from
+    /// [std::os::fortanix_sgx::mem::enclave_size](https://github.com/rust-lang/rust/blob/master/library/std/src/sys/sgx/abi/mem.rs).
+    fn enclave_size() -> usize {
+        extern "C" {
+            // filled in by the enclave loader; see `entry.S`.
+            static ENCLAVE_SIZE: usize;
+        }
+        unsafe { ENCLAVE_SIZE }
+    }
+
+    fn enclave_range() -> Range<usize> {
+        let base = image_base() as usize;
+        base..base.saturating_add(enclave_size())
+    }
+
+    pub(crate) fn module_base(ip: usize) -> Option<usize> {
+        enclave_range().contains(&ip).then(|| image_base() as usize)
+    }
+
+    pub(crate) fn stack_bounds() -> Option<Range<usize>> {
+        use std::arch::asm;
+
+        // everything from our own stack pointer up to the top of the stack is
+        // in use, so it's definitely committed.
+        let rsp: usize;
+        unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags)) };
+        let (_, top) = thread_stack();
+        let top = top?;
+        let enclave = enclave_range();
+        (enclave.start <= rsp && rsp < top && top <= enclave.end).then_some(rsp..top)
+    }
+
+    pub(crate) fn image_range() -> Option<Range<usize>> {
+        Some(enclave_range())
+    }
+
+    /// Return the enclave's heap. Vendored from
+    /// [std::os::fortanix_sgx::mem::heap_base](https://github.com/rust-lang/rust/blob/master/library/std/src/sys/sgx/abi/mem.rs)
+    /// and `heap_size`.
+    pub(crate) fn heap_range() -> Option<Range<usize>> {
+        extern "C" {
+            // filled in by the enclave loader; see `entry.S`. `HEAP_BASE` is
+            // an offset from the image base.
+            static HEAP_BASE: u64;
+            static HEAP_SIZE: usize;
+        }
+        let (offset, size) = unsafe { (HEAP_BASE, HEAP_SIZE) };
+        let base = (image_base() as usize).wrapping_add(offset as usize);
+        Some(base..base.saturating_add(size))
+    }
+
+    pub(crate) fn thread_stack() -> (Option<usize>, Option<usize>) {
+        use std::arch::asm;
+
+        // `tcsls_tos`, the first word of the thread's TCS-local storage (see
+        // `entry.S`). The loader fills in an offset from the image base, and
+        // the entry code turns it into an absolute address on the thread's
+        // first entry, before any of our code runs. The stack size is only
+        // known to the loader.
+        let top: usize;
+        unsafe {
+            asm!(
+                "mov %gs:0x0, {}",
+                out(reg) top,
+                options(att_syntax, nostack, preserves_flags, readonly),
+            )
+        };
+        (None, Some(top))
+    }
+
+    pub(crate) fn with_module_path(
+        _base: usize,
+        _f: &mut dyn FnMut(&dyn fmt::Display) -> fmt::Result,
+    ) -> fmt::Result {
+        Ok(())
+    }
+
+    pub(crate) fn gnu_build_id() -> Option<&'static [u8]> {
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{
+        ffi::{c_int, c_void, CStr, OsStr},
+        fmt,
+        mem::MaybeUninit,
+        ops::Range,
+        os::unix::ffi::OsStrExt,
+        path::Path,
+        ptr, slice,
+    };
+
+    /// Call `f` on each loaded module until it returns `true`. The first
+    /// module is always the main executable.
+    fn for_each_module<F: FnMut(usize, &libc::dl_phdr_info) -> bool>(mut f: F) {
+        struct State<F> {
+            f: F,
+            module_idx: usize,
+        }
+
+        unsafe extern "C" fn callback<F: FnMut(usize, &libc::dl_phdr_info) -> bool>(
+            info: *mut libc::dl_phdr_info,
+            _size: libc::size_t,
+            data: *mut c_void,
+        ) -> c_int {
+            let state = unsafe { &mut *data.cast::<State<F>>() };
+            let info = unsafe { &*info };
+            let done = (state.f)(state.module_idx, info);
+            state.module_idx += 1;
+            c_int::from(done)
+        }
+
+        let mut state = State {
+            f: &mut f,
+            module_idx: 0,
+        };
+        unsafe {
+            libc::dl_iterate_phdr(
+                Some(callback::<&mut F>),
+                (&mut state as *mut State<&mut F>).cast(),
+            );
+        }
+    }
+
+    fn contains(info: &libc::dl_phdr_info, ip: usize) -> bool {
+        if info.dlpi_phdr.is_null() {
+            return false;
+        }
+        let phdrs = unsafe { slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum.into()) };
+        phdrs.iter().any(|phdr| {
+            let start = (info.dlpi_addr as usize).wrapping_add(phdr.p_vaddr as usize);
+            let end = start.wrapping_add(phdr.p_memsz as usize);
+            phdr.p_type == libc::PT_LOAD && (start..end).contains(&ip)
+        })
+    }
+
+    pub(crate) fn module_base(ip: usize) -> Option<usize> {
+        let mut base = None;
+        for_each_module(|_module_idx, info| {
+            if contains(info, ip) {
+                base = Some(info.dlpi_addr as usize);
+                true
+            } else {
+                false
+            }
+        });
+        base
+    }
+
+    pub(crate) fn stack_bounds() -> Option<Range<usize>> {
+        let mut attr = MaybeUninit::<libc::pthread_attr_t>::uninit();
+        // for the main thread, glibc reads `/proc/self/maps` here, which mallocs.
+        // if we're out of memory, that just fails.
+        if unsafe { libc::pthread_getattr_np(libc::pthread_self(), attr.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        let mut stack_addr = ptr::null_mut();
+        let mut stack_size = 0;
+        let result =
+            unsafe { libc::pthread_attr_getstack(attr.as_ptr(), &mut stack_addr, &mut stack_size) };
+        unsafe { libc::pthread_attr_destroy(attr.as_mut_ptr()) };
+        if result != 0 {
+            return None;
+        }
+        let start = stack_addr as usize;
+        Some(start..start.saturating_add(stack_size))
+    }
+
+    pub(crate) fn image_range() -> Option<Range<usize>> {
+        let mut range: Option<Range<usize>> = None;
+        for_each_module(|_module_idx, info| {
+            // the first module is the main executable.
+            if info.dlpi_phdr.is_null() {
+                return true;
+            }
+            let phdrs = unsafe { slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum.into()) };
+            for phdr in phdrs.iter().filter(|phdr| phdr.p_type == libc::PT_LOAD) {
+                let start = (info.dlpi_addr as usize).wrapping_add(phdr.p_vaddr as usize);
+                let end = start.wrapping_add(phdr.p_memsz as usize);
+                range = Some(match range {
+                    Some(Range { start: lo, end: hi }) => lo.min(start)..hi.max(end),
+                    None => start..end,
+                });
+            }
+            true
+        });
+        range
+    }
+
+    pub(crate) fn heap_range() -> Option<Range<usize>> {
+        // malloc's arenas are all over the place; there's no one heap.
+        None
+    }
+
+    pub(crate) fn thread_stack() -> (Option<usize>, Option<usize>) {
+        match stack_bounds() {
+            Some(stack) => (Some(stack.start), Some(stack.end)),
+            None => (None, None),
+        }
+    }
+
+    pub(crate) fn with_module_path(
+        base: usize,
+        f: &mut dyn FnMut(&dyn fmt::Display) -> fmt::Result,
+    ) -> fmt::Result {
+        let mut result = Ok(());
+        for_each_module(|module_idx, info| {
+            if info.dlpi_addr as usize != base {
+                return false;
+            }
+            // no need to name the main executable; that's the binary we'll be
+            // symbolizing against anyway.
+            if module_idx != 0 && !info.dlpi_name.is_null() {
+                let name = unsafe { CStr::from_ptr(info.dlpi_name) }.to_bytes();
+                let path = Path::new(OsStr::from_bytes(name));
+                result = f(&path.display());
+            }
+            true
+        });
+        result
+    }
+
+    pub(crate) fn gnu_build_id() -> Option<&'static [u8]> {
+        /// `n_type` of the GNU build id note.
+        const NT_GNU_BUILD_ID: u32 = 3;
+
+        let mut build_id = None;
+        for_each_module(|_module_idx, info| {
+            // the first module is the main executable; that's all we need.
+            if info.dlpi_phdr.is_null() {
+                return true;
+            }
+            let phdrs = unsafe { slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum.into()) };
+            for phdr in phdrs.iter().filter(|phdr| phdr.p_type == libc::PT_NOTE) {
+                let start = (info.dlpi_addr as usize).wrapping_add(phdr.p_vaddr as usize);
+                // the notes stay mapped for as long as the executable does.
+                let notes: &'static [u8] =
+                    unsafe { slice::from_raw_parts(start as *const u8, phdr.p_memsz as usize) };
+                build_id = find_note(notes, b"GNU\0", NT_GNU_BUILD_ID);
+                if build_id.is_some() {
+                    break;
+                }
+            }
+            true
+        });
+        build_id
+    }
+
+    /// Find the descriptor of the ELF note with the given `name` and `n_type` in
+    /// a `PT_NOTE` segment.
+    fn find_note<'a>(mut notes: &'a [u8], name: &[u8], n_type: u32) -> Option<&'a [u8]> {
+        fn read_u32(bytes: &[u8], idx: usize) -> Option<u32> {
+            let bytes = bytes.get(idx * 4..idx * 4 + 4)?;
+            Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+        }
+        fn align4(len: usize) -> usize {
+            len.checked_add(3).map_or(usize::MAX, |len| len & !3)
+        }
+
+        while notes.len() >= 12 {
+            let namesz = read_u32(notes, 0)? as usize;
+            let descsz = read_u32(notes, 1)? as usize;
+            let note_type = read_u32(notes, 2)?;
+            let rest = &notes[12..];
+            let note_name = rest.get(..namesz)?;
+            let desc_start = align4(namesz);
+            let desc = rest.get(desc_start..desc_start.checked_add(descsz)?)?;
+            if note_name == name && note_type == n_type {
+                return Some(desc);
+            }
+            notes = rest.get(desc_start.checked_add(align4(descsz))?..)?;
+        }
+        None
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const NT_GNU_ABI_TAG: u32 = 1;
+        const NT_GNU_BUILD_ID: u32 = 3;
+
+        /// Append an ELF note, with the name and descriptor padded to 4 bytes.
+        fn push_note(notes: &mut Vec<u8>, name: &[u8], n_type: u32, desc: &[u8]) {
+            notes.extend_from_slice(&(name.len() as u32).to_ne_bytes());
+            notes.extend_from_slice(&(desc.len() as u32).to_ne_bytes());
+            notes.extend_from_slice(&n_type.to_ne_bytes());
+            for field in [name, desc] {
+                notes.extend_from_slice(field);
+                notes.resize(notes.len().next_multiple_of(4), 0);
+            }
+        }
+
+        #[test]
+        fn find_note_skips_padding() {
+            let mut notes = Vec::new();
+            // 5 byte name and 3 byte descriptor, both padded
+            push_note(&mut notes, b"Xen!\0", 7, b"abc");
+            push_note(&mut notes, b"GNU\0", NT_GNU_BUILD_ID, &[0xab; 20]);
+            assert_eq!(
+                find_note(&notes, b"GNU\0", NT_GNU_BUILD_ID),
+                Some(&[0xab; 20][..])
+            );
+
+            // a descriptor that's missing its padding at the very end is fine
+            // too
+            let mut notes = Vec::new();
+            push_note(&mut notes, b"GNU\0", NT_GNU_BUILD_ID, &[0xcd; 7]);
+            notes.pop();
+            assert_eq!(
+                find_note(&notes, b"GNU\0", NT_GNU_BUILD_ID),
+                Some(&[0xcd; 7][..])
+            );
+        }
+
+        #[test]
+        fn find_note_skips_other_notes() {
+            let mut notes = Vec::new();
+            // same name, different type
+            push_note(
+                &mut notes,
+                b"GNU\0",
+                NT_GNU_ABI_TAG,
+                &[0, 0, 0, 0, 3, 0, 0, 0],
+            );
+            // different name, same type
+            push_note(&mut notes, b"Go\0", NT_GNU_BUILD_ID, b"not a gnu build id");
+            push_note(&mut notes, b"GNU\0", NT_GNU_BUILD_ID, &[0x12; 20]);
+            assert_eq!(
+                find_note(&notes, b"GNU\0", NT_GNU_BUILD_ID),
+                Some(&[0x12; 20][..])
+            );
+
+            let mut notes = Vec::new();
+            push_note(&mut notes, b"GNU\0", NT_GNU_ABI_TAG, &[0; 16]);
+            assert_eq!(find_note(&notes, b"GNU\0", NT_GNU_BUILD_ID), None);
+            assert_eq!(find_note(&[], b"GNU\0", NT_GNU_BUILD_ID), None);
+        }
+
+        #[test]
+        fn find_note_rejects_truncated_notes() {
+            let mut notes = Vec::new();
+            push_note(&mut notes, b"GNU\0", NT_GNU_BUILD_ID, &[0x34; 20]);
+            for len in 0..notes.len() {
+                assert_eq!(
+                    find_note(&notes[..len], b"GNU\0", NT_GNU_BUILD_ID),
+                    None,
+                    "{len}"
+                );
+            }
+
+            // sizes that run past the end (or overflow) don't panic
+            for (namesz, descsz) in [(u32::MAX, 20), (4, u32::MAX), (u32::MAX, u32::MAX)] {
+                let mut notes = notes.clone();
+                notes[0..4].copy_from_slice(&namesz.to_ne_bytes());
+                notes[4..8].copy_from_slice(&descsz.to_ne_bytes());
+                assert_eq!(find_note(&notes, b"GNU\0", NT_GNU_BUILD_ID), None);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(
+    all(target_vendor = "fortanix", target_env = "sgx"),
+    target_os = "linux"
+)))]
+mod imp {
+    use std::{fmt, ops::Range};
+
+    pub(crate) fn module_base(_ip: usize) -> Option<usize> {
+        Some(0)
+    }
+
+    pub(crate) fn stack_bounds() -> Option<Range<usize>> {
+        None
+    }
+
+    pub(crate) fn image_range() -> Option<Range<usize>> {
+        None
+    }
+
+    pub(crate) fn heap_range() -> Option<Range<usize>> {
+        None
+    }
+
+    pub(crate) fn thread_stack() -> (Option<usize>, Option<usize>) {
+        (None, None)
+    }
+
+    pub(crate) fn with_module_path(
+        _base: usize,
+        _f: &mut dyn FnMut(&dyn fmt::Display) -> fmt::Result,
+    ) -> fmt::Result {
+        Ok(())
+    }
+
+    pub(crate) fn gnu_build_id() -> Option<&'static [u8]> {
+        None
+    }
+}
+
+/// Return the load base address of the image (the enclave, main executable, or
+/// shared library) containing `ip`, or `None` if it's not inside any of them
+/// (e.g. a garbage ip from a corrupt unwind). If we don't know where the images
+/// are, every ip is in an image at `0`.
+pub(crate) fn module_base(ip: usize) -> Option<usize> {
+    imp::module_base(ip)
+}
+
+/// An address range of the current thread's stack that's entirely safe to read,
+/// and includes every frame from our caller up: the whole stack on Linux, or
+/// from the current stack pointer up to the top of the stack inside SGX. `None`
+/// if we don't know.
+pub(crate) fn stack_bounds() -> Option<Range<usize>> {
+    imp::stack_bounds()
+}
+
+/// The address range of the image itself: the whole enclave (heap, stacks,
+/// and all) inside SGX, or the main executable on Linux.
+pub(crate) fn image_range() -> Option<Range<usize>> {
+    imp::image_range()
+}
+
+/// The address range of the heap, if there's just the one. Only inside SGX.
+pub(crate) fn heap_range() -> Option<Range<usize>> {
+    imp::heap_range()
+}
+
+/// The lowest address of the current thread's stack and its top (where it
+/// grows down from), whichever we know. Unlike [`stack_bounds`], this is the
+/// whole stack, not just the part that's safe to read.
+pub(crate) fn thread_stack() -> (Option<usize>, Option<usize>) {
+    imp::thread_stack()
+}
+
+/// The contents of the main executable's `.note.gnu.build-id`, if we can find
+/// it.
+pub(crate) fn gnu_build_id() -> Option<&'static [u8]> {
+    imp::gnu_build_id()
+}
+
+/// Call `f` with the path of the image loaded at `base`, if it's a shared
+/// library, so the host knows which binary to symbolize the frame's offset
+/// against.
+pub(crate) fn with_module_path(
+    base: usize,
+    f: &mut dyn FnMut(&dyn fmt::Display) -> fmt::Result,
+) -> fmt::Result {
+    imp::with_module_path(base, f)
+}
+
+/// Write out ` (<path>)` if the image loaded at `base` is a shared library. See
+/// [`with_module_path`].
+pub(crate) fn write_module_path(out: &mut dyn fmt::Write, base: usize) -> fmt::Result {
+    with_module_path(base, &mut |path| write!(out, " ({path})"))
+}
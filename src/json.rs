@@ -0,0 +1,100 @@
+//! Just enough JSON serialization for the [`ReportFormat::Json`] panic reports.
+//! We don't pull in `serde` for this so the enclave stays small.
+//!
+//! [`ReportFormat::Json`]: crate::ReportFormat::Json
+
+use std::fmt::{self, Write};
+
+use crate::{image, Frame};
+
+/// The `"schema"` version of the JSON panic reports. Bump this whenever a field
+/// changes meaning or gets removed.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+/// Escapes everything written through it for use inside a JSON string.
+struct JsonEscape<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+impl fmt::Write for JsonEscape<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' => self.0.write_str("\\\"")?,
+                '\\' => self.0.write_str("\\\\")?,
+                '\n' => self.0.write_str("\\n")?,
+                '\r' => self.0.write_str("\\r")?,
+                '\t' => self.0.write_str("\\t")?,
+                c if c.is_control() => write!(self.0, "\\u{:04x}", c as u32)?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Displays a string as a quoted and escaped JSON string.
+pub(crate) struct JsonStr<'a>(pub(crate) &'a str);
+
+impl fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        JsonEscape(f).write_str(self.0)?;
+        f.write_char('"')
+    }
+}
+
+/// Displays an optional string as a JSON string or `null`.
+pub(crate) struct JsonOptStr<'a>(pub(crate) Option<&'a str>);
+
+impl fmt::Display for JsonOptStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(s) => JsonStr(s).fmt(f),
+            None => f.write_str("null"),
+        }
+    }
+}
+
+/// Displays an optional value as a quoted and escaped JSON string, or `null`.
+pub(crate) struct JsonOptDisplay<T>(pub(crate) Option<T>);
+
+impl<T: fmt::Display> fmt::Display for JsonOptDisplay<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(value) => {
+                f.write_char('"')?;
+                write!(JsonEscape(f), "{value}")?;
+                f.write_char('"')
+            }
+            None => f.write_str("null"),
+        }
+    }
+}
+
+/// Displays the frames as a JSON array of objects, e.g.
+/// `[{"offset":"0x1b09d9"},{"offset":"0x1396f6"}]`. Each `"offset"` is a hex
+/// string, just like the text format. Frames in a shared library also get the
+/// library's path, e.g. `{"offset":"0x29d90","module":"/lib/libc.so.6"}`, and
+/// frames outside any image are marked with `"in_image":false` (their
+/// `"offset"` is the absolute ip).
+pub(crate) struct JsonFrames<'a>(pub(crate) &'a [Frame]);
+
+impl fmt::Display for JsonFrames<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('[')?;
+        for (frame_idx, frame) in self.0.iter().enumerate() {
+            if frame_idx > 0 {
+                f.write_char(',')?;
+            }
+            if frame.in_image() {
+                write!(f, "{{\"offset\":\"{:#x}\"", frame.offset())?;
+                image::with_module_path(frame.image_base, &mut |path| {
+                    write!(f, ",\"module\":{}", JsonOptDisplay(Some(path)))
+                })?;
+            } else {
+                write!(f, "{{\"offset\":\"{:#x}\",\"in_image\":false", frame.ip())?;
+            }
+            f.write_char('}')?;
+        }
+        f.write_char(']')
+    }
+}
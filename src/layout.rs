@@ -0,0 +1,135 @@
+//! The memory layout section of the panic report, for making sense of stack
+//! overflows and heap exhaustion. See
+//! [`PanicHookBuilder::memory_layout`](crate::PanicHookBuilder::memory_layout).
+
+use std::{fmt, ops::Range};
+
+use crate::image;
+
+/// Where the image, heap, and current thread's stack are, and how far down the
+/// stack the panic happened.
+pub(crate) struct MemoryLayout {
+    image: Option<Range<usize>>,
+    heap: Option<Range<usize>>,
+    stack_start: Option<usize>,
+    stack_end: Option<usize>,
+    /// The panicking frame's stack pointer, or `0` if we don't know.
+    sp: usize,
+}
+
+impl MemoryLayout {
+    /// Look up the current layout. `sp` is the panicking frame's
+    /// [`sp`](crate::Frame::sp).
+    ///
+    /// On Linux, finding the main thread's stack mallocs (see
+    /// [`image::stack_bounds`]), so only call this if the layout was asked for.
+    pub(crate) fn current(sp: usize) -> Self {
+        let (stack_start, stack_end) = image::thread_stack();
+        Self {
+            image: image::image_range(),
+            heap: image::heap_range(),
+            stack_start,
+            stack_end,
+            sp,
+        }
+    }
+
+    /// How many bytes of the stack are in use, above the panicking frame.
+    fn stack_depth(&self) -> Option<usize> {
+        let stack_end = self.stack_end?;
+        (self.sp != 0 && self.sp <= stack_end).then(|| stack_end - self.sp)
+    }
+}
+
+/// Displays the layout as the `memory layout:` section of a text panic report,
+/// e.g.
+///
+/// ```text
+/// memory layout:
+///   image: base 0x7f3a00000000, size 0x4000000
+///   heap: base 0x7f3a00200000, size 0x2000000
+///   stack: ?..0x7f3a03fc0000, sp 0x7f3a03fbf7a0 (0x860 bytes deep)
+/// ```
+///
+/// Anything we don't know is left out, or `?` for the bottom of the stack.
+pub(crate) struct DisplayMemoryLayout<'a>(pub(crate) &'a MemoryLayout);
+
+impl fmt::Display for DisplayMemoryLayout<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layout = self.0;
+        writeln!(f, "memory layout:")?;
+        for (name, range) in [("image", &layout.image), ("heap", &layout.heap)] {
+            if let Some(range) = range {
+                writeln!(
+                    f,
+                    "  {name}: base {:#x}, size {:#x}",
+                    range.start,
+                    range.len()
+                )?;
+            }
+        }
+        if layout.stack_start.is_none() && layout.stack_end.is_none() && layout.sp == 0 {
+            return Ok(());
+        }
+        f.write_str("  stack: ")?;
+        match layout.stack_start {
+            Some(start) => write!(f, "{start:#x}..")?,
+            None => f.write_str("?..")?,
+        }
+        match layout.stack_end {
+            Some(end) => write!(f, "{end:#x}")?,
+            None => f.write_str("?")?,
+        }
+        if layout.sp != 0 {
+            write!(f, ", sp {:#x}", layout.sp)?;
+        }
+        if let Some(depth) = layout.stack_depth() {
+            write!(f, " ({depth:#x} bytes deep)")?;
+        }
+        writeln!(f)
+    }
+}
+
+/// Displays the layout as the `,"memory_layout":{..}` field of a JSON panic
+/// report, or `,"memory_layout":null` if it wasn't asked for. Every address and
+/// size is a hex string, or `null` if we don't know it.
+pub(crate) struct JsonMemoryLayout<'a>(pub(crate) Option<&'a MemoryLayout>);
+
+impl fmt::Display for JsonMemoryLayout<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Hex(Option<usize>);
+
+        impl fmt::Display for Hex {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self.0 {
+                    Some(value) => write!(f, "\"{value:#x}\""),
+                    None => f.write_str("null"),
+                }
+            }
+        }
+
+        let Some(layout) = self.0 else {
+            return f.write_str(",\"memory_layout\":null");
+        };
+        f.write_str(",\"memory_layout\":{")?;
+        for (name, range) in [("image", &layout.image), ("heap", &layout.heap)] {
+            match range {
+                Some(range) => write!(
+                    f,
+                    "\"{name}\":{{\"base\":{},\"size\":{}}},",
+                    Hex(Some(range.start)),
+                    Hex(Some(range.len())),
+                )?,
+                None => write!(f, "\"{name}\":null,")?,
+            }
+        }
+        write!(
+            f,
+            "\"stack\":{{\"start\":{},\"end\":{}}},\"sp\":{},\"stack_depth\":{}}}",
+            Hex(layout.stack_start),
+            Hex(layout.stack_end),
+            Hex((layout.sp != 0).then_some(layout.sp)),
+            Hex(layout.stack_depth()),
+        )
+    }
+}
@@ -45,90 +45,376 @@
 //!    9: 0x13410e
 //! ```
 //!
-//! To get human readable symbol names and locations from these raw ips, you may
-//! wish to use the `stack-trace-resolve` utility that comes with the Fortanix
-//! EDP.
+//! To write the reports somewhere else, limit the number of frames, etc...,
+//! configure the hook with `PanicHook::builder()` instead:
 //!
-//! For example:
+//! ```rust,no_run
+//! use sgx_panic_backtrace::{Output, PanicHook};
+//!
+//! PanicHook::builder()
+//!     .output(Output::Stderr)
+//!     .frame_limit(32)
+//!     .install();
+//! ```
+//!
+//! For log pipelines, `.format(ReportFormat::Json)` prints each report as a
+//! single line of JSON instead.
+//!
+//! Panic messages can contain secrets (e.g. an `expect()` on a value holding key
+//! material), and everything the hook prints goes to the untrusted host. So in
+//! release builds, the hook only prints the panic location by default. See
+//! `Redaction` for the other options, which you can pick with
+//! `.redaction(..)` or the `redact-*` cargo features.
+//!
+//! To keep even the panic locations and frame offsets from the host, enable the
+//! `encrypt` feature and seal each report to your own X25519 public key. The hook
+//! then prints only an armored `-----BEGIN SGX PANIC REPORT-----` blob, which
+//! `sgx-panic-backtrace-resolve --key` decrypts and symbolizes:
+//!
+//! ```bash
+//! $ sgx-panic-backtrace-keygen panic-report.key
+//! $ ftxsgx-runner <my-enclave-bin>.sgxs \
+//!     | sgx-panic-backtrace-resolve --key panic-report.key <my-enclave-bin>
+//! ```
+//!
+//! ```rust,ignore
+//! PanicHook::builder()
+//!     .encrypt_to(PANIC_REPORT_PUBLIC_KEY)
+//!     .install();
+//! ```
+//!
+//! The frames inside the panic hook, the unwinder, and std's panic machinery are
+//! skipped, so the first frame is the panic call site. To print every frame
+//! (with its absolute address, image base, and symbol address) instead, or no
+//! frames at all:
+//!
+//! ```rust,no_run
+//! use sgx_panic_backtrace::BacktraceStyle;
+//! sgx_panic_backtrace::set_backtrace_style(BacktraceStyle::Full);
+//! ```
+//!
+//! Or, without rebuilding, set `SGX_PANIC_BACKTRACE` to `off`, `short`, or
+//! `full` in the enclave's environment (which the EDP runner forwards). Like
+//! `RUST_BACKTRACE`, `0` means off.
+//!
+//! If the unwinder gives up after a frame or two (e.g. incomplete `.eh_frame`
+//! info), build with `-C force-frame-pointers=yes`. By default, a backtrace that
+//! stops that early also walks the frame pointer chain, checking every saved `rbp`
+//! against the stack bounds before following it, and uses that instead if it
+//! finds more frames. See `set_frame_pointers()` to always or never walk it.
+//!
+//! The stack is walked with the `backtrace` crate by default. To keep the enclave
+//! smaller, turn off default features and enable `unwinder-libunwind` to call
+//! libunwind's `_Unwind_Backtrace` directly, or enable neither to only walk frame
+//! pointers:
+//!
+//! ```toml
+//! [dependencies]
+//! sgx-panic-backtrace = { version = "0.1.0", default-features = false, features = ["unwinder-libunwind"] }
+//! ```
+//!
+//! To capture a backtrace without panicking, e.g. to attach it to your own error
+//! types or logs, use `RawBacktrace::capture()`. It prints in the same format, so
+//! it can be symbolized the same way:
+//!
+//! ```rust
+//! let backtrace = sgx_panic_backtrace::RawBacktrace::capture();
+//! println!("{backtrace}");
+//! ```
+//!
+//! Or, to just print one out (with a label and the call site) and carry on, use the
+//! `backtrace!()` macro, which takes the same arguments as `format!()`:
+//!
+//! ```rust
+//! use sgx_panic_backtrace::backtrace;
+//!
+//! let retries = 3;
+//! backtrace!("giving up after {retries} retries");
+//! ```
+//!
+//! Allocation failures don't go through the panic hook; std just prints
+//! `memory allocation of N bytes failed` and aborts. Wrap your global allocator in
+//! a `TracingAllocator` to print a backtrace of the failed allocation first:
+//!
+//! ```rust
+//! use sgx_panic_backtrace::TracingAllocator;
+//! use std::alloc::System;
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: TracingAllocator<System> = TracingAllocator::new(System);
+//! ```
+//!
+//! To get human readable symbol names and locations from these raw ips, pipe the
+//! enclave output through the `sgx-panic-backtrace-resolve` utility that comes
+//! with this crate. It passes everything through untouched, except the frames
+//! in each `stack backtrace:` block, which it symbolizes using the DWARF debug
+//! info in the (unstripped) enclave ELF binary.
+//!
+//! ```bash
+//! $ cargo install sgx-panic-backtrace --features host
+//! $ ftxsgx-runner <my-enclave-bin>.sgxs | sgx-panic-backtrace-resolve <my-enclave-bin>
+//!
+//! enclave: panicked at 'foo', bar.rs:10:5
+//! stack backtrace:
+//!    0: 0x1b09d9 - my_enclave::bar
+//!              at src/bar.rs:10:5
+//!    1: 0x1396f6 - my_enclave::main
+//!              at src/main.rs:4:5
+//! ...
+//! ```
+//!
+//! The `stack-trace-resolve` utility that comes with the Fortanix EDP works
+//! too.
+//!
+//! A frame whose ip isn't inside the enclave image (or any loaded module on Linux),
+//! e.g. garbage from a corrupt unwind, is printed as `?? 0x...` with its absolute
+//! ip, and left unsymbolized rather than pointing at some unrelated function.
+//! Tracing stops after a couple of those in a row.
+//!
+//! To make sure each backtrace gets symbolized against the right build, a
+//! `build id:` line goes right before the `stack backtrace:` header. On Linux, it's
+//! the executable's GNU build id. Inside SGX, use the `set_panic_hook!()` macro
+//! instead, which sets the build id to your crate's name and version (plus an
+//! optional suffix, e.g. `set_panic_hook!(env!("GIT_SHA"))`):
+//!
+//! ```rust,no_run
+//! sgx_panic_backtrace::set_panic_hook!();
+//! ```
+//!
+//! `sgx-panic-backtrace-resolve` leaves any backtrace with a different GNU build id
+//! than the enclave ELF unsymbolized, rather than printing the wrong symbols.
+//!
+//! To keep the ELFs of older releases around, add each release build to a symbol
+//! store, a directory keyed by build id (debuginfod's cache layout works too). The
+//! resolver then picks the right ELF for each backtrace on its own:
+//!
+//! ```bash
+//! $ sgx-panic-backtrace-store add --build-id my-enclave-0.3.1 symbols <my-enclave-bin>
+//! $ sgx-panic-backtrace-resolve --store symbols < enclave.log
+//! ```
+//!
+//! Without `--build-id`, the ELF is stored under its GNU build id.
+//!
+//! Each panic report also carries a `fingerprint:`, a hash of the panic location
+//! and the top few frames, so you can group the same crash across many enclave
+//! instances:
 //!
 //! ```bash
-//! $ ftxsgx-runner <my-enclave-bin>.sgxs | stack-trace-resolve <my-enclave-bin>
+//! $ sgx-panic-backtrace-buckets logs/*.log
+//! fingerprint 7a647461073b64e9: 312 reports
+//! enclave panic: panicked at src/bar.rs:10:5:
+//! ...
+//! ```
+//!
+//! A bare stack often doesn't say which request the enclave was serving. Wrap work in
+//! `with_context()` and leave `breadcrumb!()`s along the way, and the panic report
+//! lists the panicking thread's current contexts and most recent breadcrumbs under
+//! the backtrace. Both live in small fixed-size per-thread buffers, so they don't
+//! allocate past their first use on each thread:
+//!
+//! ```rust
+//! use sgx_panic_backtrace::{breadcrumb, with_context};
+//!
+//! let tenant_id = 42;
+//! with_context(format_args!("handling attestation for {tenant_id}"), || {
+//!     breadcrumb!("verified quote");
+//!     // ...
+//! });
+//! ```
+//!
+//! To tell a stack overflow or heap exhaustion apart from an ordinary panic, turn
+//! on `.memory_layout(true)`. Each report then lists the base and size of the
+//! enclave image and heap, the panicking thread's stack, and how deep its stack
+//! pointer went, under the backtrace:
+//!
+//! ```text
+//! memory layout:
+//!   image: base 0x7f3a00000000, size 0x4000000
+//!   heap: base 0x7f3a00200000, size 0x2000000
+//!   stack: ?..0x7f3a03fc0000, sp 0x7f3a03fbf7a0 (0x860 bytes deep)
+//! ```
+//!
+//! When a request loop catches panics to carry on, `catch_unwind_with_backtrace()`
+//! returns the panic's location and raw backtrace along with the payload, since the
+//! stack is gone by the time `catch_unwind` returns. `sgx_panic_backtrace::spawn()`
+//! does the same for the `Err` from joining a thread. Displaying it redacts the
+//! panic message the same way the hook's report does:
+//!
+//! ```rust
+//! sgx_panic_backtrace::set_panic_hook();
+//!
+//! if let Err(caught) = sgx_panic_backtrace::catch_unwind_with_backtrace(|| {
+//!     // handle a request...
+//! }) {
+//!     eprintln!("request failed: {caught}");
+//! }
 //! ```
+//!
+//! `std::backtrace` doesn't work inside SGX either, so for errors that should
+//! remember where they came from, wrap them in `Traced<E>`. Converting with `?`
+//! captures a raw backtrace, printed after the error with `{:#}`:
+//!
+//! ```rust
+//! use sgx_panic_backtrace::Traced;
+//!
+//! fn parse_port(port: &str) -> Result<u16, Traced<std::num::ParseIntError>> {
+//!     Ok(port.parse()?)
+//! }
+//!
+//! if let Err(err) = parse_port("http") {
+//!     eprintln!("bad port: {err:#}");
+//! }
+//! ```
+//!
+//! Outside SGX, on Linux (including library OSes like Gramine and Occlum), the
+//! offsets are relative to the main executable's (randomized) load address, so
+//! the same workflow works on a normal dev box too. Frames inside a shared
+//! library are followed by the library's path, e.g.
+//! `   8: 0x2724a (/lib/x86_64-linux-gnu/libc.so.6)`.
+
+use std::{
+    env,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+pub use crate::{
+    alloc::TracingAllocator,
+    build_id::set_build_id,
+    catch::{catch_unwind_with_backtrace, spawn, CaughtPanic, JoinHandle, PanicLocation},
+    context::{breadcrumb, push_context, with_context, ContextGuard},
+    frame_pointer::{get_frame_pointers, set_frame_pointers, FramePointers},
+    hook::{Output, PanicHook, PanicHookBuilder, Redaction, ReportFormat},
+    print::print_backtrace,
+    raw_backtrace::RawBacktrace,
+    trace::Frame,
+    traced::Traced,
+};
+
+mod alloc;
+#[cfg(feature = "encrypt")]
+mod armor;
+mod buf;
+mod build_id;
+mod catch;
+mod context;
+mod fnv;
+mod frame_pointer;
+mod hook;
+#[cfg(feature = "host")]
+pub mod host;
+mod image;
+mod json;
+mod layout;
+mod lock;
+mod print;
+mod raw_backtrace;
+mod trace;
+mod traced;
+mod unwinder;
 
-use std::{io::Write, panic};
+/// The line printed right before the raw backtrace frames. The host-side
+/// symbolizer looks for this line.
+pub(crate) const BACKTRACE_HEADER: &str = "stack backtrace:";
 
-/// Return the base address of the currently loaded SGX enclave binary. Vendoring
-/// this lets us avoid requiring the unstable `sgx_platform` feature.
+/// The line in each panic report with the crash [fingerprint], e.g.
+/// `fingerprint: 5f0c2e8a9d41b7e3`. The host-side bucketing looks for this.
 ///
-/// This is copied from: [std::os::fortanix_sgx::mem::image_base](https://github.com/rust-lang/rust/blob/master/library/std/src/sys/sgx/abi/mem.rs#L37)
-// NOTE: Do not remove inline: will result in relocation failure.
-#[cfg(all(target_vendor = "fortanix", target_env = "sgx"))]
-#[inline(always)]
-fn image_base() -> u64 {
-    use std::arch::asm;
+/// [fingerprint]: PanicHook#crash-fingerprints
+pub(crate) const FINGERPRINT_PREFIX: &str = "fingerprint: ";
 
-    let base: u64;
-    unsafe {
-        asm!(
-            // `IMAGE_BASE` is defined here:
-            // [std/src/sys/sgx/abi/entry.S](https://github.com/rust-lang/rust/blob/master/library/std/src/sys/sgx/abi/entry.S#L5)
-            "lea IMAGE_BASE(%rip), {}",
-            lateout(reg) base,
-            options(att_syntax, nostack, preserves_flags, nomem, pure),
-        )
-    };
-    base
-}
+/// The environment variable the panic hook reads its [`BacktraceStyle`] from
+/// when it's installed.
+const BACKTRACE_STYLE_VAR: &str = "SGX_PANIC_BACKTRACE";
 
-#[cfg(not(all(target_vendor = "fortanix", target_env = "sgx")))]
-fn image_base() -> u64 {
-    0
+/// Controls how much of the backtrace the panic hook prints.
+///
+/// Like `RUST_BACKTRACE`, this can be set with the `SGX_PANIC_BACKTRACE`
+/// environment variable (which the EDP runner forwards into the enclave): `0` or
+/// `off`, `full`, or anything else for `short`. It's read when the panic hook is
+/// installed, and overrides any earlier [`set_backtrace_style`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BacktraceStyle {
+    /// Don't print any frames. Panic reports still have the panic location
+    /// and [fingerprint](PanicHook#crash-fingerprints), so the panic hook
+    /// still walks the stack to compute the fingerprint.
+    /// [`RawBacktrace::capture`] doesn't capture anything, though.
+    Off,
+    /// Skip the frames inside the panic hook, the unwinder, and std's panic
+    /// machinery, so the first frame printed is the panic call site.
+    #[default]
+    Short,
+    /// Print every frame, including the panic hook and unwinder frames. Each
+    /// frame also gets its absolute instruction pointer, image base, and
+    /// symbol address, e.g.
+    /// `   0: 0x1b09d9 [ip 0x7f0a2e5b09d9, base 0x7f0a2e400000, symbol 0x7f0a2e5b0990]`.
+    Full,
 }
 
-/// Trace each frame and print each relative instruction pointer offset. These
-/// offsets should be symbolized these outside the enclave.
-fn print_backtrace_frames() {
-    println!("stack backtrace:");
+impl BacktraceStyle {
+    /// Parse a `SGX_PANIC_BACKTRACE` value, the same way std parses
+    /// `RUST_BACKTRACE`.
+    fn from_env_value(value: &str) -> Self {
+        match value {
+            "0" | "off" => Self::Off,
+            "full" => Self::Full,
+            _ => Self::Short,
+        }
+    }
 
-    let mut frame_idx: usize = 0;
-    unsafe {
-        backtrace::trace_unsynchronized(|frame| {
-            let base_addr = image_base() as usize;
+    /// The style set in the environment, if any.
+    pub(crate) fn from_env() -> Option<Self> {
+        let value = env::var_os(BACKTRACE_STYLE_VAR)?;
+        Some(Self::from_env_value(value.to_str().unwrap_or_default()))
+    }
+}
 
-            // we need the ip offsets relative to the binary base address.
-            let ip = (frame.ip() as usize).saturating_sub(base_addr);
+static BACKTRACE_STYLE: AtomicU8 = AtomicU8::new(BacktraceStyle::Short as u8);
 
-            println!("{frame_idx:>4}: {ip:#x}");
-            frame_idx += 1;
+/// Set the [`BacktraceStyle`] used by the panic hook. Defaults to
+/// [`BacktraceStyle::Short`], or whatever `SGX_PANIC_BACKTRACE` says when the
+/// panic hook is installed.
+pub fn set_backtrace_style(style: BacktraceStyle) {
+    BACKTRACE_STYLE.store(style as u8, Ordering::Relaxed);
+}
 
-            // TODO(phlip9): be smarter and ignore frames inside the
-            // panic/backtrace code.
-            // keep tracing until we run out of frames
-            true
-        })
+/// Get the [`BacktraceStyle`] currently used by the panic hook.
+pub fn get_backtrace_style() -> BacktraceStyle {
+    match BACKTRACE_STYLE.load(Ordering::Relaxed) {
+        x if x == BacktraceStyle::Off as u8 => BacktraceStyle::Off,
+        x if x == BacktraceStyle::Full as u8 => BacktraceStyle::Full,
+        _ => BacktraceStyle::Short,
     }
-    println!();
 }
 
 /// Set a panic hook that will print out the panic and raw backtrace addresses
 /// when the enclave panics. These addresses will need to be symbolized to human-
 /// readable symbol names and locations outside the enclave with a tool like
 /// `addr2line`.
+///
+/// This is the zero-config shortcut for `PanicHook::builder().install()`. See
+/// [`PanicHook::builder`] to configure the output, frame limit, etc...
 pub fn set_panic_hook() {
-    let prev_hook = panic::take_hook();
-    panic::set_hook(Box::new(move |panic_info| {
-        // The default panic hook also doesn't print out the panic message, so
-        // let's do that here.
-        println!("enclave panic: {panic_info}");
-
-        // trace the stack frames and print them out
-        print_backtrace_frames();
+    PanicHook::builder().install();
+}
 
-        // enclave's about to abort. let's try to flush stdout so we get the
-        // full panic message out. ignore any errors so we don't double panic.
-        let _ = std::io::stdout().flush();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // continue the default panic behaviour.
-        prev_hook(panic_info);
-    }));
+    #[test]
+    fn backtrace_style_from_env_value() {
+        for (value, style) in [
+            ("0", BacktraceStyle::Off),
+            ("off", BacktraceStyle::Off),
+            ("full", BacktraceStyle::Full),
+            ("1", BacktraceStyle::Short),
+            ("short", BacktraceStyle::Short),
+            ("", BacktraceStyle::Short),
+            // case-sensitive, like `RUST_BACKTRACE`
+            ("Full", BacktraceStyle::Short),
+            ("OFF", BacktraceStyle::Short),
+        ] {
+            assert_eq!(BacktraceStyle::from_env_value(value), style, "{value:?}");
+        }
+    }
 }
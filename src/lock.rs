@@ -0,0 +1,150 @@
+//! The global locks that serialize stack traces and panic reports across
+//! threads:
+//!
+//! + [`UNWINDER`] keeps threads from running the unwinder concurrently. It's
+//!   only held while tracing the stack, so it's never held for long.
+//! + [`REPORTS`] keeps two threads panicking at once from interleaving their
+//!   reports. It's held while writing to the (possibly slow) sink, but nothing
+//!   that just traces the stack waits on it.
+//!
+//! A thread holding [`REPORTS`] may take [`UNWINDER`], but never the other way
+//! around. We're inside the panic hook, so the locks can't ever deadlock:
+//!
+//! + They're reentrant, so a thread that already holds one (e.g. the hook
+//!   printing a backtrace) can take it again.
+//! + Waiting for one times out after [`LOCK_TIMEOUT`], in case the thread
+//!   holding it is stuck, e.g. writing to a blocked sink. We then carry on
+//!   without the lock; an interleaved report beats no report.
+
+use std::{
+    hint,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(all(target_vendor = "fortanix", target_env = "sgx"))]
+use crate::image;
+
+/// How long to wait for another thread's report before giving up on the lock.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Spin this many times before yielding to other threads.
+const SPIN_LIMIT: usize = 64;
+
+/// Serializes stack traces. See the [module docs](self).
+pub(crate) static UNWINDER: Lock = Lock::new();
+
+/// Serializes panic reports and printed backtraces. See the
+/// [module docs](self).
+pub(crate) static REPORTS: Lock = Lock::new();
+
+/// A reentrant spin lock that gives up after [`LOCK_TIMEOUT`].
+pub(crate) struct Lock {
+    /// The [`thread_id`] of the thread holding the lock, or `0` if it's
+    /// unlocked.
+    owner: AtomicUsize,
+    /// How many times the owning thread has taken the lock. Only ever touched
+    /// by the owner.
+    depth: AtomicUsize,
+}
+
+/// A non-zero id that's unique among the running threads, without touching a
+/// thread-local: inside SGX, a thread-local's first use on each thread
+/// allocates, and that's no good in the panic hook or the allocator.
+///
+/// Inside SGX, this is the top of the thread's stack. Each TCS gets its own
+/// stack, and the address is right there in the TCS-local storage.
+#[cfg(all(target_vendor = "fortanix", target_env = "sgx"))]
+pub(crate) fn thread_id() -> usize {
+    // always there inside SGX.
+    image::thread_stack().1.unwrap_or(usize::MAX)
+}
+
+/// A non-zero id that's unique among the running threads, without touching a
+/// thread-local: `pthread_self`, which is just a register read.
+#[cfg(target_os = "linux")]
+pub(crate) fn thread_id() -> usize {
+    unsafe { libc::pthread_self() as usize }
+}
+
+/// A non-zero id that's unique among the running threads: the address of a
+/// thread-local. Unlike `thread::current().id()`, this never allocates.
+#[cfg(not(any(
+    all(target_vendor = "fortanix", target_env = "sgx"),
+    target_os = "linux",
+)))]
+pub(crate) fn thread_id() -> usize {
+    thread_local! {
+        static THREAD_MARKER: u8 = const { 0 };
+    }
+    THREAD_MARKER.with(|marker| marker as *const u8 as usize)
+}
+
+/// Holds a [`Lock`], if we managed to take it, until dropped.
+#[must_use]
+pub(crate) struct Guard<'a> {
+    lock: &'a Lock,
+    locked: bool,
+}
+
+impl Lock {
+    const fn new() -> Self {
+        Self {
+            owner: AtomicUsize::new(0),
+            depth: AtomicUsize::new(0),
+        }
+    }
+
+    /// Take the lock. See the [module docs](self).
+    pub(crate) fn lock(&self) -> Guard<'_> {
+        let me = thread_id();
+        if self.owner.load(Ordering::Relaxed) == me {
+            self.depth.fetch_add(1, Ordering::Relaxed);
+            return Guard {
+                lock: self,
+                locked: true,
+            };
+        }
+
+        let mut deadline = None;
+        for attempt in 0.. {
+            if self
+                .owner
+                .compare_exchange_weak(0, me, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                self.depth.store(1, Ordering::Relaxed);
+                return Guard {
+                    lock: self,
+                    locked: true,
+                };
+            }
+
+            if attempt < SPIN_LIMIT {
+                hint::spin_loop();
+                continue;
+            }
+            // only check the time once we're actually waiting; it's a usercall
+            // inside SGX.
+            let deadline = *deadline.get_or_insert_with(|| Instant::now() + LOCK_TIMEOUT);
+            if Instant::now() >= deadline {
+                break;
+            }
+            thread::yield_now();
+        }
+
+        Guard {
+            lock: self,
+            locked: false,
+        }
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        if self.locked && self.lock.depth.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.lock.owner.store(0, Ordering::Release);
+        }
+    }
+}
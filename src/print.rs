@@ -0,0 +1,95 @@
+//! [`print_backtrace`] and [`backtrace!`](crate::backtrace!): print a backtrace
+//! without panicking.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    panic::Location,
+};
+
+use crate::{
+    buf::{StackWriter, REPORT_BUF_SIZE},
+    get_backtrace_style, hook, lock,
+    trace::{self, DisplayFrames, Frame, MAX_FRAMES},
+    BacktraceStyle,
+};
+
+/// Print a labelled backtrace of the current thread's stack, in the same format
+/// as the panic hook, then carry on. It goes to the installed
+/// [`PanicHook`](crate::PanicHook)'s [`Output`](crate::Output), or stdout if
+/// there isn't one.
+///
+/// ```text
+/// enclave backtrace: at src/bar.rs:10:5:
+/// suspicious state
+/// stack backtrace:
+///    0: 0x1b09d9
+///    1: 0x1396f6
+/// ...
+/// ```
+///
+/// The first frame is the caller of `print_backtrace`, unless the
+/// [`BacktraceStyle`] is [`Full`](BacktraceStyle::Full). See also
+/// [`backtrace!`](crate::backtrace!), which takes a format string.
+///
+/// ```rust
+/// sgx_panic_backtrace::print_backtrace("suspicious state");
+/// ```
+// always inlined, so there's no frame of ours between `print_backtrace_inner`
+// and the caller.
+#[inline(always)]
+#[track_caller]
+pub fn print_backtrace<T: fmt::Display>(label: T) {
+    print_backtrace_inner(&label, Location::caller());
+}
+
+#[inline(never)]
+fn print_backtrace_inner(label: &dyn fmt::Display, location: &Location<'_>) {
+    let mut frames = [Frame::default(); MAX_FRAMES];
+    let num_frames = trace::capture_frames(&mut frames);
+    let frames = match get_backtrace_style() {
+        BacktraceStyle::Off => &[],
+        BacktraceStyle::Short => trace::frames_after(
+            &frames[..num_frames],
+            print_backtrace_inner as *const () as usize,
+        ),
+        BacktraceStyle::Full => &frames[..num_frames],
+    };
+
+    // don't interleave with any panic reports or other backtraces.
+    let _guard = lock::REPORTS.lock();
+    // a diagnostic isn't worth panicking over.
+    let _ = hook::with_installed_output(|out| write_backtrace(out, label, location, frames));
+}
+
+fn write_backtrace(
+    out: &mut dyn Write,
+    label: &dyn fmt::Display,
+    location: &Location<'_>,
+    frames: &[Frame],
+) -> io::Result<()> {
+    let mut out = StackWriter::<_, REPORT_BUF_SIZE>::new(out);
+    writeln!(out, "enclave backtrace: at {location}:\n{label}")?;
+    writeln!(out, "{}", DisplayFrames(frames))?;
+    out.flush()
+}
+
+/// Print a labelled backtrace and carry on. Takes the same arguments
+/// as [`format!`], or nothing at all. See [`print_backtrace`].
+///
+/// ```rust
+/// use sgx_panic_backtrace::backtrace;
+///
+/// let retries = 3;
+/// backtrace!();
+/// backtrace!("giving up after {retries} retries");
+/// ```
+#[macro_export]
+macro_rules! backtrace {
+    () => {
+        $crate::print_backtrace("backtrace")
+    };
+    ($($arg:tt)+) => {
+        $crate::print_backtrace(::std::format_args!($($arg)+))
+    };
+}
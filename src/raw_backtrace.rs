@@ -0,0 +1,67 @@
+//! [`RawBacktrace`]: capture a backtrace as a value, without panicking.
+
+use std::fmt;
+
+use crate::{
+    get_backtrace_style,
+    trace::{self, DisplayFrames, Frame, MAX_FRAMES},
+    BacktraceStyle,
+};
+
+/// A captured backtrace of raw stack frames. Like the panic hook, it doesn't
+/// symbolize anything; the [`Display`](fmt::Display) output is in the same
+/// `stack backtrace:` format, so it can be symbolized outside the enclave the
+/// same way.
+///
+/// ```rust
+/// use sgx_panic_backtrace::RawBacktrace;
+///
+/// let backtrace = RawBacktrace::capture();
+/// println!("{backtrace}");
+/// ```
+#[derive(Clone, Debug)]
+pub struct RawBacktrace {
+    frames: Vec<Frame>,
+}
+
+impl RawBacktrace {
+    /// Capture a backtrace of the current thread's stack. The first frame is
+    /// the caller of `capture`, unless the [`BacktraceStyle`] is
+    /// [`Full`](BacktraceStyle::Full). If it's [`Off`](BacktraceStyle::Off),
+    /// nothing is captured.
+    #[inline(never)]
+    pub fn capture() -> Self {
+        Self::capture_after(Self::capture as *const () as usize)
+    }
+
+    /// Capture a backtrace, starting from the caller of the function at address
+    /// `marker`, unless the [`BacktraceStyle`] is [`Full`](BacktraceStyle::Full).
+    pub(crate) fn capture_after(marker: usize) -> Self {
+        if get_backtrace_style() == BacktraceStyle::Off {
+            return Self::from_frames(Vec::new());
+        }
+        let mut frames = [Frame::default(); MAX_FRAMES];
+        let num_frames = trace::capture_frames(&mut frames);
+        let frames = match get_backtrace_style() {
+            BacktraceStyle::Off => &[],
+            BacktraceStyle::Short => trace::frames_after(&frames[..num_frames], marker),
+            BacktraceStyle::Full => &frames[..num_frames],
+        };
+        Self::from_frames(frames.to_vec())
+    }
+
+    pub(crate) fn from_frames(frames: Vec<Frame>) -> Self {
+        Self { frames }
+    }
+
+    /// The captured frames, starting from the top of the stack.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+}
+
+impl fmt::Display for RawBacktrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        DisplayFrames(&self.frames).fmt(f)
+    }
+}
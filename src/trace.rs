@@ -0,0 +1,407 @@
+//! Capturing raw stack frames and trimming off the frames that belong to the
+//! panic hook, the unwinder, and std's panic machinery.
+//!
+//! We don't symbolize anything inside the enclave, so we can't just look for
+//! `std::panicking::*` in the frame names like std does. Instead, we compare
+//! each frame's `symbol_address` (the start of its enclosing function) against
+//! function addresses we already know:
+//!
+//! 1. our own functions, which we can just take the address of, and
+//! 2. std's panic runtime functions, which we learn by triggering and catching
+//!    a few panics when the hook is installed (see [`calibrate`]).
+
+use std::{
+    cell::Cell,
+    fmt,
+    hint::black_box,
+    panic,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Once,
+    },
+};
+
+use crate::{
+    build_id::{BuildId, BUILD_ID_PREFIX},
+    frame_pointer::{get_frame_pointers, FramePointers},
+    get_backtrace_style, image, lock,
+    unwinder::{DefaultUnwinder, FramePointerWalker, Unwinder},
+    BacktraceStyle, BACKTRACE_HEADER,
+};
+
+/// The max number of frames we'll capture for a single backtrace.
+pub(crate) const MAX_FRAMES: usize = 128;
+
+/// With [`FramePointers::Fallback`], only walk the frame pointer chain if the
+/// unwinder found fewer frames than this, i.e. it gave up after a frame or two.
+/// Walking the chain isn't free (on Linux, finding the main thread's stack
+/// allocates), so we don't on every capture.
+const FEW_FRAMES: usize = 4;
+
+/// Stop tracing after this many frames in a row with an ip outside any loaded
+/// image. A corrupt unwind tends to keep producing garbage.
+const MAX_INVALID_FRAMES: usize = 2;
+
+/// The max number of distinct std panic runtime functions we'll remember.
+const MAX_PANIC_RUNTIME_FNS: usize = 32;
+
+/// Only look for std panic runtime frames this far past the panic hook's frame.
+/// Keeps us from trimming off any user frames that happen to be deeper in the
+/// stack.
+const PANIC_RUNTIME_WINDOW: usize = 24;
+
+/// A single raw stack frame.
+#[derive(Clone, Copy, Default)]
+pub struct Frame {
+    pub(crate) ip: usize,
+    pub(crate) sp: usize,
+    pub(crate) symbol_address: usize,
+    pub(crate) image_base: usize,
+    pub(crate) in_image: bool,
+}
+
+impl Frame {
+    pub(crate) const ZERO: Self = Self::new(0, 0, 0);
+
+    /// A frame we haven't found the image for yet. See [`capture_frames`].
+    pub(crate) const fn new(ip: usize, sp: usize, symbol_address: usize) -> Self {
+        Self {
+            ip,
+            sp,
+            symbol_address,
+            image_base: 0,
+            in_image: false,
+        }
+    }
+
+    /// The frame's instruction pointer offset, relative to the base of the
+    /// image it's in. This is what needs to be symbolized outside the enclave.
+    pub fn offset(&self) -> usize {
+        self.ip.saturating_sub(self.image_base)
+    }
+
+    /// The frame's absolute instruction pointer.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// The frame's stack pointer (its canonical frame address, i.e. the stack
+    /// pointer right before the call into the frame below it), or `0` if the
+    /// unwinder didn't say.
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    /// The absolute address of the start of the frame's enclosing function,
+    /// or `0` if the unwinder couldn't find it.
+    pub fn symbol_address(&self) -> usize {
+        self.symbol_address
+    }
+
+    /// The load base address of the image containing the frame: the enclave
+    /// image inside SGX, or the main executable or shared library on Linux.
+    /// `0` if we don't know.
+    pub fn image_base(&self) -> usize {
+        self.image_base
+    }
+
+    /// Whether the frame's ip is inside a loaded image. If not, the ip is
+    /// probably garbage from a corrupt unwind, and its offset is just the
+    /// absolute ip, which would symbolize to something unrelated.
+    pub fn in_image(&self) -> bool {
+        self.in_image
+    }
+}
+
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Frame")
+            .field("offset", &format_args!("{:#x}", self.offset()))
+            .field("ip", &format_args!("{:#x}", self.ip))
+            .field("sp", &format_args!("{:#x}", self.sp))
+            .field(
+                "symbol_address",
+                &format_args!("{:#x}", self.symbol_address),
+            )
+            .field("in_image", &self.in_image)
+            .finish()
+    }
+}
+
+/// The `symbol_address` of each std panic runtime function between the panic
+/// hook and the panic call site. Filled in once by [`calibrate`].
+static PANIC_RUNTIME_FNS: [AtomicUsize; MAX_PANIC_RUNTIME_FNS] =
+    [const { AtomicUsize::new(0) }; MAX_PANIC_RUNTIME_FNS];
+static NUM_PANIC_RUNTIME_FNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Trace the current stack, filling `frames` from the top of the stack down.
+/// Returns the number of frames captured.
+///
+/// Uses the [`DefaultUnwinder`], the [frame pointer walker](FramePointerWalker),
+/// or both, depending on [`get_frame_pointers`].
+///
+/// Doesn't allocate, so it's safe to call from inside the panic hook, except on
+/// Linux when it walks the frame pointer chain (see [`FEW_FRAMES`]). Holds
+/// [`lock::UNWINDER`] so only one thread runs the unwinder at a time.
+#[inline(never)]
+pub(crate) fn capture_frames(frames: &mut [Frame]) -> usize {
+    let _guard = lock::UNWINDER.lock();
+    match get_frame_pointers() {
+        FramePointers::Never => capture_with::<DefaultUnwinder>(frames),
+        FramePointers::Fallback => {
+            let num_frames = capture_with::<DefaultUnwinder>(frames);
+            if num_frames >= FEW_FRAMES.min(frames.len()) {
+                return num_frames;
+            }
+            // only overwrite the unwinder's frames if the chain is longer.
+            let mut num_walked: usize = 0;
+            FramePointerWalker::trace(&mut |_frame| {
+                num_walked += 1;
+                num_walked < frames.len()
+            });
+            if num_walked > num_frames {
+                capture_with::<FramePointerWalker>(frames)
+            } else {
+                num_frames
+            }
+        }
+        FramePointers::Always => capture_with::<FramePointerWalker>(frames),
+    }
+}
+
+/// Fill `frames` using unwinder `U`, and find the image each frame is in.
+/// Returns the number of frames captured.
+fn capture_with<U: Unwinder>(frames: &mut [Frame]) -> usize {
+    let mut num_frames: usize = 0;
+    let mut num_invalid: usize = 0;
+    U::trace(&mut |mut frame| {
+        // a zero ip is just the end of the stack, e.g. the unwinder reached the
+        // thread's entry point.
+        if frame.ip == 0 {
+            return false;
+        }
+        let Some(slot) = frames.get_mut(num_frames) else {
+            // out of space
+            return false;
+        };
+        match image::module_base(frame.ip) {
+            Some(image_base) => {
+                frame.image_base = image_base;
+                frame.in_image = true;
+                num_invalid = 0;
+            }
+            None => num_invalid += 1,
+        }
+        *slot = frame;
+        num_frames += 1;
+        // keep tracing until we run out of frames, or into garbage
+        num_invalid < MAX_INVALID_FRAMES
+    });
+    num_frames
+}
+
+/// Displays the `stack backtrace:` header and each frame's instruction pointer
+/// offset, relative to the image base. These offsets should be symbolized
+/// outside the enclave. Frames in a shared library are followed by the
+/// library's path. Frames outside any image get their absolute ip instead,
+/// marked with `??`, e.g. `   7: ?? 0x10`.
+///
+/// If we know the build id, it goes right before the header, so the host knows
+/// which binary to symbolize against.
+///
+/// With [`BacktraceStyle::Full`], each frame also gets its absolute addresses.
+/// With [`BacktraceStyle::Off`], this displays nothing at all.
+pub(crate) struct DisplayFrames<'a>(pub(crate) &'a [Frame]);
+
+impl fmt::Display for DisplayFrames<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let style = get_backtrace_style();
+        if style == BacktraceStyle::Off {
+            return Ok(());
+        }
+        if let Some(build_id) = BuildId::get() {
+            writeln!(f, "{BUILD_ID_PREFIX}{build_id}")?;
+        }
+        writeln!(f, "{BACKTRACE_HEADER}")?;
+        for (frame_idx, frame) in self.0.iter().enumerate() {
+            if frame.in_image {
+                write!(f, "{frame_idx:>4}: {:#x}", frame.offset())?;
+                image::write_module_path(f, frame.image_base)?;
+            } else {
+                write!(f, "{frame_idx:>4}: ?? {:#x}", frame.ip)?;
+            }
+            if style == BacktraceStyle::Full {
+                write!(
+                    f,
+                    " [ip {:#x}, base {:#x}, symbol {:#x}]",
+                    frame.ip, frame.image_base, frame.symbol_address,
+                )?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Return the frames after the frame for the function at address `marker`, or
+/// all the frames if it's not on the stack (e.g., the unwinder couldn't find
+/// the enclosing function for each frame).
+pub(crate) fn frames_after(frames: &[Frame], marker: usize) -> &[Frame] {
+    match frames.iter().position(|f| f.symbol_address == marker) {
+        Some(idx) => &frames[idx + 1..],
+        None => frames,
+    }
+}
+
+/// Return only the frames below the panic call site. `marker` is the address
+/// of the function in the panic hook that captured the frames.
+///
+/// If we haven't been able to [`calibrate`] (e.g. `panic=abort`), this only
+/// trims off our own frames and std's panic runtime frames will still show up.
+pub(crate) fn trim_panic_frames(frames: &[Frame], marker: usize) -> &[Frame] {
+    let frames = frames_after(frames, marker);
+
+    // there may be a few closure and `Box<dyn Fn>` shim frames between us and
+    // the std panic runtime, so skip to the _last_ std frame near the top of
+    // the stack.
+    let window = frames.len().min(PANIC_RUNTIME_WINDOW);
+    let skip = frames[..window]
+        .iter()
+        .rposition(|f| is_panic_runtime_fn(f.symbol_address))
+        .map_or(0, |idx| idx + 1);
+
+    &frames[skip..]
+}
+
+fn is_panic_runtime_fn(symbol_address: usize) -> bool {
+    let num_fns = NUM_PANIC_RUNTIME_FNS.load(Ordering::Acquire);
+    PANIC_RUNTIME_FNS[..num_fns]
+        .iter()
+        .any(|addr| addr.load(Ordering::Relaxed) == symbol_address)
+}
+
+fn add_panic_runtime_fn(symbol_address: usize) {
+    if symbol_address == 0 || is_panic_runtime_fn(symbol_address) {
+        return;
+    }
+    // only the calibrating thread ever adds fns, so this doesn't race.
+    let num_fns = NUM_PANIC_RUNTIME_FNS.load(Ordering::Relaxed);
+    if let Some(slot) = PANIC_RUNTIME_FNS.get(num_fns) {
+        slot.store(symbol_address, Ordering::Relaxed);
+        NUM_PANIC_RUNTIME_FNS.store(num_fns + 1, Ordering::Release);
+    }
+}
+
+// The different ways user code commonly ends up in the std panic runtime. Each
+// one takes a (slightly) different path through std.
+
+#[inline(never)]
+fn panic_fmt_site() {
+    panic!("{}", black_box("calibrate"));
+}
+
+// `panic_any` is generic over the payload, so we can only learn the common
+// payload types.
+#[inline(never)]
+fn panic_any_str_site() {
+    panic::panic_any(black_box("calibrate"));
+}
+
+#[inline(never)]
+fn panic_any_string_site() {
+    panic::panic_any(black_box(String::new()));
+}
+
+#[inline(never)]
+fn option_unwrap_site() {
+    black_box(None::<u8>).unwrap();
+}
+
+#[inline(never)]
+fn option_expect_site() {
+    black_box(None::<u8>).expect("calibrate");
+}
+
+#[inline(never)]
+fn result_unwrap_site() {
+    black_box(Err::<u8, u8>(0)).unwrap();
+}
+
+#[inline(never)]
+fn index_oob_site() {
+    let xs: &[u8] = black_box(&[]);
+    black_box(xs[black_box(0)]);
+}
+
+const CALIBRATION_SITES: [fn(); 7] = [
+    panic_fmt_site,
+    panic_any_str_site,
+    panic_any_string_site,
+    option_unwrap_site,
+    option_expect_site,
+    result_unwrap_site,
+    index_oob_site,
+];
+
+thread_local! {
+    /// The calibration site that's currently panicking on this thread, if any.
+    static CALIBRATION_SITE: Cell<Option<fn()>> = const { Cell::new(None) };
+}
+
+/// Learn the addresses of std's panic runtime functions by panicking at each
+/// of our calibration sites and recording every frame between our hook and
+/// the site. Only runs once and does nothing if panics don't unwind.
+pub(crate) fn calibrate() {
+    static CALIBRATE: Once = Once::new();
+
+    if !cfg!(panic = "unwind") {
+        return;
+    }
+
+    CALIBRATE.call_once(|| {
+        // other threads could panic while we're calibrating; make sure those
+        // still go to the previous hook.
+        let prev_hook = Arc::new(panic::take_hook());
+        let hook_prev_hook = prev_hook.clone();
+        panic::set_hook(Box::new(move |panic_info| {
+            match CALIBRATION_SITE.with(Cell::get) {
+                Some(site) => record_panic_runtime_fns(site),
+                None => hook_prev_hook(panic_info),
+            }
+        }));
+
+        for site in CALIBRATION_SITES {
+            CALIBRATION_SITE.with(|cell| cell.set(Some(site)));
+            let _ = panic::catch_unwind(site);
+        }
+        CALIBRATION_SITE.with(|cell| cell.set(None));
+
+        // drop our calibration hook (and its `prev_hook` ref) and put the
+        // previous hook back.
+        drop(panic::take_hook());
+        if let Some(prev_hook) = Arc::into_inner(prev_hook) {
+            panic::set_hook(prev_hook);
+        }
+    });
+}
+
+#[inline(never)]
+fn record_panic_runtime_fns(site: fn()) {
+    let mut frames = [Frame::default(); MAX_FRAMES];
+    let num_frames = capture_frames(&mut frames);
+    let frames = frames_after(
+        &frames[..num_frames],
+        record_panic_runtime_fns as *const () as usize,
+    );
+
+    let site = site as usize;
+    let Some(site_idx) = frames.iter().position(|f| f.symbol_address == site) else {
+        // couldn't find the site (maybe it got inlined); don't trust anything
+        // else in this trace.
+        return;
+    };
+
+    for frame in &frames[..site_idx] {
+        add_panic_runtime_fn(frame.symbol_address);
+    }
+}
@@ -0,0 +1,104 @@
+//! [`Traced`]: an error wrapper that remembers where the error was created.
+
+use std::{error::Error, fmt};
+
+use crate::{trace::DisplayFrames, RawBacktrace};
+
+/// An error, plus a raw backtrace captured when it was created. Like
+/// `anyhow`'s backtraces, but it works inside SGX, where `std::backtrace`
+/// doesn't.
+///
+/// It converts `From` the wrapped error, so `?` captures a backtrace at the
+/// point the error gets converted:
+///
+/// ```rust
+/// use sgx_panic_backtrace::Traced;
+///
+/// fn parse_port(port: &str) -> Result<u16, Traced<std::num::ParseIntError>> {
+///     Ok(port.parse()?)
+/// }
+///
+/// let err = parse_port("http").unwrap_err();
+/// // just the error, like `ParseIntError`'s `Display`
+/// assert_eq!(err.to_string(), "invalid digit found in string");
+/// // the error, then the `stack backtrace:`
+/// eprintln!("{err:#}");
+/// ```
+///
+/// The alternate `{:#}` [`Display`](fmt::Display) and the
+/// [`Debug`](fmt::Debug) output follow the error with its backtrace, in the
+/// same `stack backtrace:` format as the panic hook, so it can be symbolized
+/// outside the enclave the same way.
+///
+/// Capturing walks the stack, so keep `Traced` for errors that are actually
+/// exceptional, not ones that happen on every request.
+pub struct Traced<E> {
+    error: E,
+    backtrace: RawBacktrace,
+}
+
+impl<E> Traced<E> {
+    /// Wrap `error`, capturing a backtrace starting from the caller of `new`.
+    #[inline(never)]
+    pub fn new(error: E) -> Self {
+        Self {
+            error,
+            backtrace: RawBacktrace::capture_after(Self::new as *const () as usize),
+        }
+    }
+
+    /// The wrapped error.
+    pub fn error(&self) -> &E {
+        &self.error
+    }
+
+    /// Unwrap the error, dropping the backtrace.
+    pub fn into_inner(self) -> E {
+        self.error
+    }
+
+    /// The backtrace captured when the error was created.
+    pub fn backtrace(&self) -> &RawBacktrace {
+        &self.backtrace
+    }
+}
+
+impl<E> From<E> for Traced<E> {
+    /// Wrap `error`, capturing a backtrace starting from the caller of `from`
+    /// (e.g. the function using `?`).
+    #[inline(never)]
+    fn from(error: E) -> Self {
+        Self {
+            error,
+            backtrace: RawBacktrace::capture_after(<Self as From<E>>::from as *const () as usize),
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Traced<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)?;
+        if f.alternate() {
+            write!(f, "\n{}", DisplayFrames(self.backtrace.frames()))?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: fmt::Debug> fmt::Debug for Traced<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}\n{}",
+            self.error,
+            DisplayFrames(self.backtrace.frames())
+        )
+    }
+}
+
+impl<E: Error> Error for Traced<E> {
+    // `Traced` displays as the wrapped error, so it isn't its own source.
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
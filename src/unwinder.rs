@@ -0,0 +1,137 @@
+//! The unwinder backends that actually walk the stack.
+//!
+//! Which one [`capture_frames`](crate::trace::capture_frames) uses is picked by
+//! cargo features:
+//!
+//! + `unwinder-libunwind`: call libunwind's `_Unwind_Backtrace` directly. No
+//!   extra dependencies, so it's the smallest.
+//! + `unwinder-backtrace-rs` (default): go through the `backtrace` crate.
+//! + neither: only walk the frame pointer chain (see [`frame_pointer`]).
+//!
+//! If both features are on, `unwinder-libunwind` wins. The frame pointer walker
+//! is always there, as a fallback (see
+//! [`FramePointers`](crate::FramePointers)).
+
+use crate::{frame_pointer, trace::Frame};
+
+/// A way to walk the current thread's stack.
+pub(crate) trait Unwinder {
+    /// Call `f` on each frame, starting from the top of the stack, until it
+    /// returns `false` or we run out of frames.
+    ///
+    /// Mustn't allocate, and mustn't run on more than one thread at a time (see
+    /// [`lock`](crate::lock)).
+    fn trace(f: &mut dyn FnMut(Frame) -> bool);
+}
+
+/// The unwinder picked by cargo features. See the [module docs](self).
+#[cfg(feature = "unwinder-libunwind")]
+pub(crate) type DefaultUnwinder = Libunwind;
+#[cfg(all(feature = "unwinder-backtrace-rs", not(feature = "unwinder-libunwind")))]
+pub(crate) type DefaultUnwinder = BacktraceRs;
+#[cfg(not(any(feature = "unwinder-backtrace-rs", feature = "unwinder-libunwind")))]
+pub(crate) type DefaultUnwinder = FramePointerWalker;
+
+/// Whether [`DefaultUnwinder`] is an actual unwinder, and not just the frame
+/// pointer walker.
+pub(crate) const HAS_UNWINDER: bool = cfg!(any(
+    feature = "unwinder-backtrace-rs",
+    feature = "unwinder-libunwind"
+));
+
+/// `backtrace::trace_unsynchronized`.
+#[cfg(all(feature = "unwinder-backtrace-rs", not(feature = "unwinder-libunwind")))]
+pub(crate) struct BacktraceRs;
+
+#[cfg(all(feature = "unwinder-backtrace-rs", not(feature = "unwinder-libunwind")))]
+impl Unwinder for BacktraceRs {
+    #[inline(never)]
+    fn trace(f: &mut dyn FnMut(Frame) -> bool) {
+        unsafe {
+            backtrace::trace_unsynchronized(|frame| {
+                f(Frame::new(
+                    frame.ip() as usize,
+                    frame.sp() as usize,
+                    frame.symbol_address() as usize,
+                ))
+            })
+        }
+    }
+}
+
+/// libunwind's `_Unwind_Backtrace`, called directly. This is what the
+/// `backtrace` crate ends up calling too, on every target we care about.
+#[cfg(feature = "unwinder-libunwind")]
+pub(crate) struct Libunwind;
+
+#[cfg(feature = "unwinder-libunwind")]
+impl Unwinder for Libunwind {
+    #[inline(never)]
+    fn trace(mut f: &mut dyn FnMut(Frame) -> bool) {
+        use std::ffi::{c_int, c_void};
+
+        /// `_Unwind_Reason_Code` values.
+        const _URC_NO_REASON: c_int = 0;
+        const _URC_FAILURE: c_int = 9;
+
+        extern "C" {
+            fn _Unwind_Backtrace(
+                trace: extern "C" fn(ctx: *mut c_void, arg: *mut c_void) -> c_int,
+                arg: *mut c_void,
+            ) -> c_int;
+            fn _Unwind_GetIP(ctx: *mut c_void) -> usize;
+            fn _Unwind_GetCFA(ctx: *mut c_void) -> usize;
+        }
+
+        extern "C" fn trace_fn(ctx: *mut c_void, arg: *mut c_void) -> c_int {
+            // SAFETY: `arg` is the `&mut f` we passed to `_Unwind_Backtrace`,
+            // which is still on the stack.
+            let f = unsafe { &mut *arg.cast::<&mut dyn FnMut(Frame) -> bool>() };
+            let (ip, sp) = unsafe { (_Unwind_GetIP(ctx), _Unwind_GetCFA(ctx)) };
+            if f(Frame::new(ip, sp, enclosing_function(ip))) {
+                _URC_NO_REASON
+            } else {
+                _URC_FAILURE
+            }
+        }
+
+        unsafe {
+            _Unwind_Backtrace(
+                trace_fn,
+                (&mut f as *mut &mut dyn FnMut(Frame) -> bool).cast(),
+            );
+        }
+    }
+}
+
+/// The frame pointer walker. See [`frame_pointer`].
+pub(crate) struct FramePointerWalker;
+
+impl Unwinder for FramePointerWalker {
+    fn trace(f: &mut dyn FnMut(Frame) -> bool) {
+        frame_pointer::walk(f)
+    }
+}
+
+/// Find the start of the function containing `ip` in the `.eh_frame` info, just
+/// like the unwinder does for its frames, or `0` if it's not in there.
+#[cfg(any(
+    all(target_vendor = "fortanix", target_env = "sgx"),
+    target_os = "linux"
+))]
+pub(crate) fn enclosing_function(ip: usize) -> usize {
+    use std::ffi::c_void;
+
+    extern "C" {
+        fn _Unwind_FindEnclosingFunction(pc: *mut c_void) -> *mut c_void;
+    }
+    unsafe { _Unwind_FindEnclosingFunction(ip as *mut c_void) as usize }
+}
+
+#[cfg(not(any(
+    all(target_vendor = "fortanix", target_env = "sgx"),
+    target_os = "linux"
+)))]
+pub(crate) fn enclosing_function(_ip: usize) -> usize {
+    0
+}
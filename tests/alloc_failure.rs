@@ -0,0 +1,70 @@
+//! A failed allocation gets a backtrace on stderr, and the caller carries on.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    process::Command,
+};
+
+use sgx_panic_backtrace::TracingAllocator;
+
+/// Set in the child process, which does the actual failing allocation.
+const CHILD_VAR: &str = "SGX_PANIC_BACKTRACE_TEST_CHILD";
+
+/// Anything at least this big fails.
+const TOO_BIG: usize = 1 << 40;
+
+/// The system allocator, but it fails anything [`TOO_BIG`].
+struct Limited;
+
+unsafe impl GlobalAlloc for Limited {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() >= TOO_BIG {
+            return std::ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: TracingAllocator<Limited> = TracingAllocator::new(Limited);
+
+#[test]
+fn failed_allocation_reports_a_backtrace() {
+    if env::var_os(CHILD_VAR).is_some() {
+        let mut bytes = Vec::<u8>::new();
+        assert!(bytes.try_reserve_exact(TOO_BIG).is_err());
+        // and it can report the next one, too
+        assert!(bytes.try_reserve_exact(TOO_BIG + 1).is_err());
+        return;
+    }
+
+    let output = Command::new(env::current_exe().unwrap())
+        .args([
+            "--exact",
+            "failed_allocation_reports_a_backtrace",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .env(CHILD_VAR, "1")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+
+    for size in [TOO_BIG, TOO_BIG + 1] {
+        let header =
+            format!("enclave alloc error: memory allocation of {size} bytes (align 1) failed\n");
+        let (_, report) = stderr
+            .split_once(&header)
+            .unwrap_or_else(|| panic!("no report:\n{stderr}"));
+        // maybe after a `build id:` line
+        let (before, frames) = report.split_once("stack backtrace:\n").unwrap();
+        assert!(before.lines().count() <= 1, "{stderr}");
+        assert!(frames.starts_with("   0: 0x"), "{stderr}");
+    }
+}
@@ -0,0 +1,43 @@
+//! `backtrace!()` should write to the installed hook's output, not stdout.
+
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use sgx_panic_backtrace::{backtrace, Output, PanicHook};
+
+#[derive(Clone)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn backtrace_goes_to_the_hook_output() {
+    let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+    PanicHook::builder()
+        .output(Output::Custom(Box::new(buffer.clone())))
+        .install();
+
+    let retries = 3;
+    backtrace!("giving up after {retries} retries");
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    assert!(
+        output.starts_with("enclave backtrace: at tests/backtrace_output.rs:"),
+        "{output}"
+    );
+    assert!(output.contains("\ngiving up after 3 retries\n"), "{output}");
+    assert!(output.contains("\nstack backtrace:\n"), "{output}");
+    // the end of the stack isn't a garbage frame
+    assert!(!output.contains(": ?? 0x0\n"), "{output}");
+}
@@ -0,0 +1,59 @@
+//! A report stuck writing to a blocked output shouldn't hold up other threads
+//! tracing their stacks.
+
+use std::{
+    io::{self, Write},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use sgx_panic_backtrace::{Output, PanicHook, RawBacktrace};
+
+/// Blocks every write until it's told to go ahead.
+struct BlockedWriter {
+    entered: Sender<()>,
+    proceed: Receiver<()>,
+}
+
+impl Write for BlockedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _ = self.entered.send(());
+        let _ = self.proceed.recv();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn blocked_output_doesnt_block_backtraces() {
+    let (entered_tx, entered_rx) = mpsc::channel();
+    let (proceed_tx, proceed_rx) = mpsc::channel();
+    PanicHook::builder()
+        .output(Output::Custom(Box::new(BlockedWriter {
+            entered: entered_tx,
+            proceed: proceed_rx,
+        })))
+        .chain_prev_hook(false)
+        .install();
+
+    let panicking = thread::spawn(|| panic!("stuck reporting"));
+    // the panicking thread is now stuck writing its report
+    entered_rx.recv().unwrap();
+
+    let start = Instant::now();
+    let backtrace = RawBacktrace::capture();
+    let elapsed = start.elapsed();
+
+    // let the report finish before checking anything, so a failed assert
+    // doesn't get stuck behind it. any further writes just go through.
+    drop(proceed_tx);
+    assert!(panicking.join().is_err());
+
+    assert!(!backtrace.frames().is_empty());
+    // well under the lock timeout
+    assert!(elapsed < Duration::from_millis(500), "took {elapsed:?}");
+}
@@ -0,0 +1,107 @@
+//! Threads panicking at the same time shouldn't interleave their reports.
+
+use std::{
+    io::{self, Write},
+    sync::{Arc, Barrier, Mutex},
+    thread,
+};
+
+use sgx_panic_backtrace::{Output, PanicHook, Redaction};
+
+const NUM_THREADS: usize = 8;
+
+/// A shared in-memory sink that yields between writes, to give the other
+/// panicking threads every chance to interleave.
+#[derive(Clone)]
+struct SlowBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SlowBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        thread::yield_now();
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn is_frame_line(line: &str, frame_idx: usize) -> bool {
+    // frames outside any loaded image are marked with `??`
+    let line = line.replacen(": ?? 0x", ": 0x", 1);
+    match line.trim().split_once(": 0x") {
+        Some((idx, offset)) => {
+            // frames in shared libraries are followed by the library's path
+            let offset = offset.split_once(' ').map_or(offset, |(offset, _)| offset);
+            idx.parse() == Ok(frame_idx)
+                && !offset.is_empty()
+                && offset.bytes().all(|b| b.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+#[test]
+fn concurrent_panics_dont_interleave() {
+    let buffer = SlowBuffer(Arc::new(Mutex::new(Vec::new())));
+    PanicHook::builder()
+        .output(Output::Custom(Box::new(buffer.clone())))
+        .redaction(Redaction::Full)
+        .chain_prev_hook(false)
+        .install();
+
+    let barrier = Arc::new(Barrier::new(NUM_THREADS));
+    let handles = (0..NUM_THREADS)
+        .map(|thread_idx| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                panic!("thread {thread_idx} panicked");
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        assert!(handle.join().is_err());
+    }
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let reports = output
+        .split("enclave panic: ")
+        .filter(|report| !report.is_empty())
+        .collect::<Vec<_>>();
+    assert_eq!(reports.len(), NUM_THREADS, "output:\n{output}");
+
+    let mut seen = [false; NUM_THREADS];
+    for report in reports {
+        let mut lines = report.lines();
+        let location = lines.next().unwrap();
+        assert!(location.starts_with("panicked at "), "report:\n{report}");
+
+        let message = lines.next().unwrap();
+        let thread_idx = message
+            .strip_prefix("thread ")
+            .and_then(|rest| rest.strip_suffix(" panicked"))
+            .and_then(|idx| idx.parse::<usize>().ok())
+            .unwrap_or_else(|| panic!("bad message line: {message:?}"));
+        assert!(!seen[thread_idx], "duplicate report:\n{report}");
+        seen[thread_idx] = true;
+
+        let mut line = lines.next();
+        assert!(
+            line.is_some_and(|line| line.starts_with("fingerprint: ")),
+            "report:\n{report}"
+        );
+        line = lines.next();
+        if line.is_some_and(|line| line.starts_with("build id: ")) {
+            line = lines.next();
+        }
+        assert_eq!(line, Some("stack backtrace:"), "report:\n{report}");
+        let mut num_frames = 0;
+        for line in lines.filter(|line| !line.is_empty()) {
+            assert!(is_frame_line(line, num_frames), "report:\n{report}");
+            num_frames += 1;
+        }
+        assert!(num_frames > 0, "report:\n{report}");
+    }
+}
@@ -0,0 +1,61 @@
+//! A JSON report should be one line of valid JSON, however awkward the panic
+//! message is.
+
+use std::{
+    io::{self, Write},
+    panic,
+    sync::{Arc, Mutex},
+};
+
+use sgx_panic_backtrace::{Output, PanicHook, Redaction, ReportFormat};
+
+#[derive(Clone)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn multi_line_message_stays_on_one_line() {
+    let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+    PanicHook::builder()
+        .output(Output::Custom(Box::new(buffer.clone())))
+        .format(ReportFormat::Json)
+        .redaction(Redaction::Full)
+        .chain_prev_hook(false)
+        .install();
+
+    let message = "bad \"config\":\nfoo = \"bar\"\n\tbaz = {\"qux\": 1}";
+    assert!(panic::catch_unwind(|| panic!("{message}")).is_err());
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let report = output.strip_suffix('\n').unwrap();
+    assert!(!report.contains('\n'), "{output}");
+
+    let report: serde_json::Value = serde_json::from_str(report).unwrap();
+    assert_eq!(report["schema"], 1);
+    assert_eq!(report["type"], "panic");
+    assert_eq!(report["message"], message);
+
+    let location = &report["location"];
+    assert_eq!(location["file"], "tests/json_report.rs");
+    assert!(location["line"].is_u64(), "{location}");
+    assert!(location["column"].is_u64(), "{location}");
+
+    assert_eq!(report["thread"], "multi_line_message_stays_on_one_line");
+
+    let frames = report["frames"].as_array().unwrap();
+    assert!(!frames.is_empty(), "{report}");
+    for frame in frames {
+        let offset = frame["offset"].as_str().unwrap();
+        assert!(offset.starts_with("0x"), "{frame}");
+    }
+}
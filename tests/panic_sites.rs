@@ -0,0 +1,111 @@
+//! Both the hook's report and [`catch_unwind_with_backtrace`] should start at
+//! the panic call site, with every std panic runtime frame trimmed off, for
+//! each of the common ways into the panic runtime.
+
+use std::{
+    hint::black_box,
+    io::{self, Write},
+    panic,
+    sync::{Arc, Mutex},
+};
+
+use sgx_panic_backtrace::{
+    catch_unwind_with_backtrace, set_backtrace_style, BacktraceStyle, Output, PanicHook,
+};
+
+#[derive(Clone)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[inline(never)]
+fn panic_site() {
+    panic!("{}", black_box("boom"));
+}
+
+#[inline(never)]
+fn expect_site() {
+    black_box(None::<u8>).expect("boom");
+}
+
+#[inline(never)]
+fn unwrap_site() {
+    black_box(Err::<u8, u8>(0)).unwrap();
+}
+
+#[inline(never)]
+fn index_oob_site() {
+    let xs: &[u8] = black_box(&[]);
+    black_box(xs[black_box(0)]);
+}
+
+const SITES: [(&str, fn()); 4] = [
+    ("panic!", panic_site),
+    ("expect", expect_site),
+    ("unwrap", unwrap_site),
+    ("index out of bounds", index_oob_site),
+];
+
+/// The offset of the first frame in a text report.
+fn first_offset(report: &str) -> usize {
+    let (_, frames) = report
+        .split_once("stack backtrace:\n")
+        .unwrap_or_else(|| panic!("no backtrace:\n{report}"));
+    let frame = frames.lines().next().unwrap();
+    let offset = frame
+        .split_once(": 0x")
+        .map(|(_, offset)| offset)
+        .unwrap_or_else(|| panic!("no offset: {frame}"));
+    usize::from_str_radix(offset, 16).unwrap()
+}
+
+#[test]
+fn frames_start_at_the_panic_site() {
+    let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+    PanicHook::builder()
+        .output(Output::Custom(Box::new(buffer.clone())))
+        .chain_prev_hook(false)
+        .install();
+    set_backtrace_style(BacktraceStyle::Short);
+
+    for (name, site) in SITES {
+        buffer.0.lock().unwrap().clear();
+        let caught = catch_unwind_with_backtrace(site).unwrap_err();
+
+        let frames = caught.backtrace().frames();
+        assert!(!frames.is_empty(), "{name}: no frames");
+        assert_eq!(
+            frames[0].symbol_address(),
+            site as usize,
+            "{name}: caught backtrace doesn't start at the site",
+        );
+
+        // the report has no symbol addresses, but its first frame should be
+        // the same return address in the site.
+        let report = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            first_offset(&report),
+            frames[0].offset(),
+            "{name}: report doesn't start at the site:\n{report}",
+        );
+
+        // and the same through plain `catch_unwind`, outside any catch scope
+        buffer.0.lock().unwrap().clear();
+        assert!(panic::catch_unwind(site).is_err());
+        let report = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            first_offset(&report),
+            frames[0].offset(),
+            "{name}: report doesn't start at the site:\n{report}",
+        );
+    }
+}
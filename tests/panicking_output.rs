@@ -0,0 +1,108 @@
+//! std aborts as soon as anything panics inside a panic hook, so a custom
+//! output that panics loses the report. With the stderr notice on, the panic
+//! location should still make it out to stderr first.
+
+use std::{
+    env,
+    io::{self, Write},
+    process::{Command, Output as ProcessOutput},
+};
+
+use sgx_panic_backtrace::{Output, PanicHook, Redaction, ReportFormat};
+
+/// Set in the child process, which does the actual panicking, to the report
+/// format.
+const CHILD_VAR: &str = "SGX_PANIC_BACKTRACE_TEST_CHILD";
+
+struct PanickingWriter;
+
+impl Write for PanickingWriter {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        panic!("sink exploded");
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `test` again in a child process that panics with a [`PanickingWriter`]
+/// output and reports in `format`.
+fn run_child(test: &str, format: &str) -> ProcessOutput {
+    Command::new(env::current_exe().unwrap())
+        .args(["--exact", test, "--nocapture", "--test-threads=1"])
+        .env(CHILD_VAR, format)
+        .output()
+        .unwrap()
+}
+
+/// If we're the child, install the hook and panic.
+fn maybe_panic_as_child() {
+    let Some(format) = env::var_os(CHILD_VAR) else {
+        return;
+    };
+    let format = match format.to_str() {
+        Some("json") => ReportFormat::Json,
+        _ => ReportFormat::Text,
+    };
+    PanicHook::builder()
+        .output(Output::Custom(Box::new(PanickingWriter)))
+        .format(format)
+        .redaction(Redaction::LocationOnly)
+        .chain_prev_hook(false)
+        .stderr_notice(true)
+        .install();
+    panic!("the original panic");
+}
+
+#[test]
+fn panicking_output_still_reports_the_location() {
+    maybe_panic_as_child();
+
+    let output = run_child("panicking_output_still_reports_the_location", "text");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    // the writer panicking inside the hook aborts the process
+    assert!(!output.status.success(), "{stderr}");
+    assert!(stderr.contains("sink exploded"), "{stderr}");
+
+    let line = stderr
+        .lines()
+        .find(|line| line.starts_with("enclave panic: panicked at "))
+        .unwrap_or_else(|| panic!("no location line:\n{stderr}"));
+    let location = line
+        .strip_prefix("enclave panic: panicked at ")
+        .and_then(|rest| rest.split_once(" (fingerprint: "))
+        .map(|(location, _)| location)
+        .unwrap();
+    assert!(location.starts_with("tests/panicking_output.rs:"), "{line}");
+    // and it's out before the writer ever runs
+    assert!(stderr.find(line) < stderr.find("sink exploded"), "{stderr}");
+}
+
+#[test]
+fn panicking_output_still_reports_the_location_as_json() {
+    maybe_panic_as_child();
+
+    let output = run_child(
+        "panicking_output_still_reports_the_location_as_json",
+        "json",
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success(), "{stderr}");
+    assert!(stderr.contains("sink exploded"), "{stderr}");
+
+    let line = stderr
+        .lines()
+        .find(|line| line.starts_with('{'))
+        .unwrap_or_else(|| panic!("no notice:\n{stderr}"));
+    let notice: serde_json::Value = serde_json::from_str(line).unwrap();
+    assert_eq!(notice["type"], "panic_notice", "{line}");
+    assert_eq!(notice["encrypted"], false, "{line}");
+    assert_eq!(notice["location"]["file"], "tests/panicking_output.rs");
+    assert!(notice["fingerprint"].is_string(), "{line}");
+    assert!(stderr.find(line) < stderr.find("sink exploded"), "{stderr}");
+    // nothing in text
+    assert!(!stderr.contains("enclave panic:"), "{stderr}");
+}
@@ -0,0 +1,143 @@
+//! Redacted reports keep the panic message out of the text and JSON output.
+
+use std::{
+    io::{self, Write},
+    panic,
+    sync::{Arc, Mutex},
+};
+
+use sgx_panic_backtrace::{
+    catch_unwind_with_backtrace, Output, PanicHook, Redaction, ReportFormat,
+};
+
+#[derive(Clone)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const SECRET: &str = "hunter2";
+
+/// Install a hook with `redaction` and `format`, panic with `message`, and
+/// return the report.
+fn report(redaction: Redaction, format: ReportFormat, message: &str) -> String {
+    let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+    PanicHook::builder()
+        .output(Output::Custom(Box::new(buffer.clone())))
+        .redaction(redaction)
+        .format(format)
+        .chain_prev_hook(false)
+        .install();
+    assert!(panic::catch_unwind(|| panic!("{message}")).is_err());
+    let _ = panic::take_hook();
+    let output = buffer.0.lock().unwrap().clone();
+    String::from_utf8(output).unwrap()
+}
+
+/// The hex message hash on the text report's `[redacted message ..]` line.
+fn text_message_hash(report: &str) -> &str {
+    let line = report.lines().nth(1).unwrap();
+    line.strip_prefix("[redacted message ")
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or_else(|| panic!("no redacted message line:\n{report}"))
+}
+
+// one test, since each report installs its own hook.
+#[test]
+fn redacted_reports() {
+    // text
+    let location_only = report(Redaction::LocationOnly, ReportFormat::Text, SECRET);
+    assert!(!location_only.contains(SECRET), "{location_only}");
+    assert!(
+        location_only.starts_with("enclave panic: panicked at tests/redaction.rs:"),
+        "{location_only}"
+    );
+    assert!(
+        !location_only.contains("[redacted message"),
+        "{location_only}"
+    );
+
+    let hashed = report(Redaction::HashedMessage, ReportFormat::Text, SECRET);
+    assert!(!hashed.contains(SECRET), "{hashed}");
+    assert!(
+        hashed.starts_with("enclave panic: panicked at tests/redaction.rs:"),
+        "{hashed}"
+    );
+    let hash = text_message_hash(&hashed);
+    assert_eq!(hash.len(), 16, "{hashed}");
+    assert!(hash.bytes().all(|b| b.is_ascii_hexdigit()), "{hashed}");
+    // the same message always gets the same hash, and a different one doesn't
+    let again = report(Redaction::HashedMessage, ReportFormat::Text, SECRET);
+    assert_eq!(text_message_hash(&again), hash);
+    let other = report(Redaction::HashedMessage, ReportFormat::Text, "hunter3");
+    assert_ne!(text_message_hash(&other), hash);
+
+    // JSON
+    let json = |redaction| {
+        let report = report(redaction, ReportFormat::Json, SECRET);
+        assert!(!report.contains(SECRET), "{report}");
+        serde_json::from_str::<serde_json::Value>(&report).unwrap()
+    };
+
+    let location_only = json(Redaction::LocationOnly);
+    assert!(location_only["message"].is_null(), "{location_only}");
+    assert!(location_only["message_hash"].is_null(), "{location_only}");
+    assert_eq!(location_only["location"]["file"], "tests/redaction.rs");
+
+    let hashed = json(Redaction::HashedMessage);
+    assert!(hashed["message"].is_null(), "{hashed}");
+    assert_eq!(hashed["message_hash"], hash, "{hashed}");
+    assert_eq!(hashed["location"]["file"], "tests/redaction.rs");
+
+    // a caught panic displays the same way as the report
+    for redaction in [
+        Redaction::LocationOnly,
+        Redaction::HashedMessage,
+        Redaction::Full,
+    ] {
+        PanicHook::builder()
+            .output(Output::Custom(Box::new(io::sink())))
+            .redaction(redaction)
+            .chain_prev_hook(false)
+            .install();
+        let caught = catch_unwind_with_backtrace(|| panic!("{SECRET}")).unwrap_err();
+        let _ = panic::take_hook();
+
+        // the message is still there if you ask for it
+        assert_eq!(caught.message(), Some(SECRET));
+
+        let display = caught.to_string();
+        let debug = format!("{caught:?}");
+        assert!(
+            display.starts_with("panicked at tests/redaction.rs:"),
+            "{display}"
+        );
+        match redaction {
+            Redaction::LocationOnly => {
+                assert!(!display.contains(SECRET), "{display}");
+                assert!(!display.contains("[redacted message"), "{display}");
+                assert!(!debug.contains(SECRET), "{debug}");
+            }
+            Redaction::HashedMessage => {
+                assert!(!display.contains(SECRET), "{display}");
+                assert!(
+                    display.contains(&format!("\n[redacted message {hash}]\n")),
+                    "{display}"
+                );
+                assert!(!debug.contains(SECRET), "{debug}");
+            }
+            Redaction::Full => {
+                assert!(display.contains(&format!(":\n{SECRET}\n")), "{display}");
+                assert!(debug.contains(SECRET), "{debug}");
+            }
+        }
+    }
+}